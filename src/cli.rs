@@ -0,0 +1,348 @@
+use std::{collections::HashMap, error::Error, fs, path::{Path, PathBuf}, sync::Arc};
+
+use image::ImageFormat;
+use pollster::block_on;
+use serde::Serialize;
+use serde_json::Value;
+
+use simple_image_app::color::{process_color_pipeline, ColorMode};
+use simple_image_app::parallel::set_thread_count;
+use simple_image_app::process::{image_open, image_save, GrayscaleMethod, ProcessParameters, ProcessType};
+use simple_image_app::progress::ProcessProgress;
+use simple_image_app::recipe::recipe_load;
+
+const DEFAULT_OUTPUT_FORMAT: &str = "png";
+const DEFAULT_JPEG_QUALITY: u8 = 90;
+/// Fields of a step that a `--process TYPE:VARIANT` suffix can pick.
+const VARIANT_FIELDS: &[&str] = &["method", "rank", "operator"];
+
+const USAGE: &str = "\
+Usage: simple-image-cli [OPTIONS] <INPUT>...
+
+Runs a processing pipeline on every input without opening a window.
+Inputs may be file paths or glob patterns such as \"scans/*.tif\".
+
+Options:
+  -o, --output <DIR>        Directory the results are written to (required)
+  -r, --recipe <FILE>       Append the steps of a .json or .toml recipe
+  -p, --process <TYPE>      Append a step, e.g. Binarization, OtsuBinarization,
+                            AdaptiveBinarization, ConvolveFilterAVG, GaussianBlur,
+                            RankFilter, EdgeDetection, Canny, Crop; a suffix such as
+                            AdaptiveBinarization:Niblack, RankFilter:Maximum or
+                            EdgeDetection:Scharr picks the method, rank or operator
+  -s, --set <KEY=VALUE>     Set a parameter of the last step added with --process,
+                            e.g. threshold=20000 or parameters.window_size=31
+  -g, --grayscale <METHOD>  Gray conversion the pipeline starts from: Rec601, Rec709,
+                            Average, Lightness, Red, Green or Blue (default: Rec709)
+  -c, --color <MODE>        Grayscale runs on the gray conversion, Channels on the red,
+                            green and blue channels and Lightness on the Lab L channel
+                            of the input (default: Grayscale)
+  -f, --format <EXT>        Output format: png, tiff, bmp or jpg (default: png)
+      --jpeg-quality <N>    JPEG quality from 1 to 100 (default: 90)
+  -j, --threads <N>         Worker threads, 0 uses all CPU cores (default: 0)
+  -h, --help                Print this help";
+
+struct CliOptions {
+    inputs: Vec<String>,
+    steps: Vec<ProcessParameters>,
+    grayscale_method: GrayscaleMethod,
+    color_mode: ColorMode,
+    output_dir: PathBuf,
+    format: String,
+    jpeg_quality: u8,
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    std::process::exit(run(&args));
+}
+
+/// Runs the command line `args` and returns the exit code.
+fn run(args: &[String]) -> i32 {
+    let options = match parse_args(args) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return 0;
+        },
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return 2;
+        }
+    };
+    let paths = match expand_inputs(&options.inputs) {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        }
+    };
+    if let Err(e) = check_output_paths(&paths, &options) {
+        eprintln!("error: {}", e);
+        return 1;
+    }
+    if let Err(e) = fs::create_dir_all(&options.output_dir) {
+        eprintln!("error: {}: {}", options.output_dir.display(), e);
+        return 1;
+    }
+
+    let mut failed = false;
+    for path in paths {
+        match process_file(&path, &options) {
+            Ok(output_path) => println!("{} -> {}", path.display(), output_path.display()),
+            Err(e) => {
+                eprintln!("error: {}: {}", path.display(), e);
+                failed = true;
+            }
+        }
+    }
+    if failed { 1 } else { 0 }
+}
+
+fn process_file(path: &Path, options: &CliOptions) -> Result<PathBuf, Box<dyn Error>> {
+    let mut image_panel_data = image_open(&path.to_string_lossy())?;
+    if options.color_mode == ColorMode::Grayscale {
+        image_panel_data = image_panel_data.to_grayscale(options.grayscale_method);
+    }
+    let progress = Arc::new(ProcessProgress::new());
+    let results = block_on(process_color_pipeline(image_panel_data, options.steps.clone(), options.color_mode, progress))?;
+
+    let output_path = output_path(path, options);
+    image_save(results.last().unwrap(), &output_path.to_string_lossy(), options.jpeg_quality)?;
+    Ok(output_path)
+}
+
+fn output_path(path: &Path, options: &CliOptions) -> PathBuf {
+    let file_stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    options.output_dir.join(format!("{}.{}", file_stem, options.format))
+}
+
+/// Fails when two inputs, such as `a/x.png` and `b/x.png`, would be written
+/// to the same output file.
+fn check_output_paths(paths: &[PathBuf], options: &CliOptions) -> Result<(), String> {
+    let mut inputs: HashMap<PathBuf, &Path> = HashMap::new();
+    for path in paths {
+        let output_path = output_path(path, options);
+        match inputs.insert(output_path.clone(), path) {
+            Some(other) if other != path.as_path() => {
+                return Err(format!("{} and {} would both be written to {}", other.display(), path.display(), output_path.display()));
+            },
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+/// Returns `None` when only the help text was requested.
+fn parse_args(args: &[String]) -> Result<Option<CliOptions>, String> {
+    let mut inputs = Vec::new();
+    let mut steps: Vec<Value> = Vec::new();
+    let mut last_process: Option<usize> = None;
+    let mut output_dir = None;
+    let mut format = String::from(DEFAULT_OUTPUT_FORMAT);
+    let mut jpeg_quality = DEFAULT_JPEG_QUALITY;
+    let mut grayscale_method = GrayscaleMethod::default();
+    let mut color_mode = ColorMode::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().cloned().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => output_dir = Some(PathBuf::from(value(arg)?)),
+            "-r" | "--recipe" => {
+                let recipe_steps = recipe_load(&value(arg)?).map_err(|e| e.to_string())?;
+                for step in recipe_steps {
+                    steps.push(serde_json::to_value(step).map_err(|e| e.to_string())?);
+                }
+                last_process = None;
+            },
+            "-p" | "--process" => {
+                let name = value(arg)?;
+                let process_type = process_type_by_name(&name).ok_or(format!("unknown process type: {}", name))?;
+                steps.push(serde_json::to_value(process_type.default_parameters()).map_err(|e| e.to_string())?);
+                last_process = Some(steps.len() - 1);
+            },
+            "-s" | "--set" => {
+                let assignment = value(arg)?;
+                let index = last_process.ok_or("--set must follow --process")?;
+                set_parameter(&mut steps[index], &assignment)?;
+            },
+            "-g" | "--grayscale" => {
+                let name = value(arg)?;
+                grayscale_method = variant_by_name(GrayscaleMethod::ALL, &name).ok_or(format!("unknown grayscale method: {}", name))?;
+            },
+            "-c" | "--color" => {
+                let name = value(arg)?;
+                color_mode = variant_by_name(ColorMode::ALL, &name).ok_or(format!("unknown color mode: {}", name))?;
+            },
+            "-f" | "--format" => {
+                let name = value(arg)?;
+                format = name.trim_start_matches('.').to_ascii_lowercase();
+                // The formats `image_save` writes; checked here so that a typo fails before any processing.
+                if !matches!(ImageFormat::from_extension(&format), Some(ImageFormat::Png | ImageFormat::Tiff | ImageFormat::Bmp | ImageFormat::Jpeg)) {
+                    return Err(format!("unsupported output format: {}, use png, tiff, bmp or jpg", name));
+                }
+            },
+            "-j" | "--threads" => {
+                let threads = value(arg)?.parse().map_err(|_| String::from("--threads must be a number"))?;
+                set_thread_count(threads);
+            },
+            "--jpeg-quality" => {
+                jpeg_quality = value(arg)?.parse().map_err(|_| String::from("--jpeg-quality must be a number from 1 to 100"))?;
+            },
+            _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+            _ => inputs.push(arg.clone()),
+        }
+    }
+
+    let output_dir = output_dir.ok_or("--output is required")?;
+    if inputs.is_empty() {
+        return Err(String::from("no input files given"));
+    }
+    if steps.is_empty() {
+        return Err(String::from("no steps given, use --recipe or --process"));
+    }
+    let steps = steps.into_iter()
+        .map(|step| serde_json::from_value(step).map_err(|e| format!("invalid parameters: {}", e)))
+        .collect::<Result<Vec<ProcessParameters>, String>>()?;
+    Ok(Some(CliOptions { inputs, steps, grayscale_method, color_mode, output_dir, format, jpeg_quality }))
+}
+
+/// Matches `name` against the `type` tag used in recipe files. A `:VARIANT`
+/// suffix picks the method, rank or operator, as in `AdaptiveBinarization:Niblack`,
+/// so that the step starts from the defaults of that variant.
+fn process_type_by_name(name: &str) -> Option<ProcessType> {
+    let (type_name, variant) = match name.split_once(':') {
+        Some((type_name, variant)) => (type_name, Some(variant)),
+        None => (name, None),
+    };
+    let matches = |value: &Value, name: &str| value.as_str().is_some_and(|tag| tag.eq_ignore_ascii_case(name));
+    ProcessType::ALL.iter().copied().find(|process_type| {
+        let Ok(Value::Object(fields)) = serde_json::to_value(process_type.default_parameters()) else {
+            return false;
+        };
+        fields.get("type").is_some_and(|tag| matches(tag, type_name))
+            && variant.is_none_or(|variant| VARIANT_FIELDS.iter().any(|field| fields.get(*field).is_some_and(|value| matches(value, variant))))
+    })
+}
+
+/// Matches `name` against the serialized names of `variants`.
+fn variant_by_name<T: Serialize + Copy>(variants: &[T], name: &str) -> Option<T> {
+    variants.iter().copied().find(|variant| {
+        serde_json::to_value(variant).ok()
+            .and_then(|tag| tag.as_str().map(|tag| tag.eq_ignore_ascii_case(name)))
+            .unwrap_or(false)
+    })
+}
+
+/// Applies `key=value` to a serialized step; dots in the key address nested fields.
+fn set_parameter(step: &mut Value, assignment: &str) -> Result<(), String> {
+    let (key, raw_value) = assignment.split_once('=').ok_or(format!("expected KEY=VALUE, got {}", assignment))?;
+    let mut target = &mut *step;
+    for part in key.trim().split('.') {
+        target = target.get_mut(part).ok_or(format!("unknown parameter: {}", key))?;
+    }
+    let raw_value = raw_value.trim();
+    *target = serde_json::from_str(raw_value).unwrap_or_else(|_| Value::String(raw_value.to_string()));
+    Ok(())
+}
+
+fn expand_inputs(inputs: &[String]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut paths = Vec::new();
+    for input in inputs {
+        if Path::new(input).exists() {
+            paths.push(PathBuf::from(input));
+            continue;
+        }
+        let matches = glob::glob(input)?.collect::<Result<Vec<PathBuf>, _>>()?;
+        if matches.is_empty() {
+            return Err(format!("no files match {}", input).into());
+        }
+        paths.extend(matches);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_image_app::process::{AdaptiveMethod, BinarizationParameters, RankKind};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn options(format: &str) -> CliOptions {
+        CliOptions {
+            inputs: Vec::new(),
+            steps: Vec::new(),
+            grayscale_method: GrayscaleMethod::default(),
+            color_mode: ColorMode::default(),
+            output_dir: PathBuf::from("out"),
+            format: String::from(format),
+            jpeg_quality: DEFAULT_JPEG_QUALITY,
+        }
+    }
+
+    #[test]
+    fn parse_args_builds_the_pipeline() {
+        let options = parse_args(&args(&["-o", "out", "-p", "Binarization", "-s", "threshold=123", "-f", ".TIF", "a.png", "b.png"]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.inputs, ["a.png", "b.png"]);
+        assert_eq!(options.output_dir, PathBuf::from("out"));
+        assert_eq!(options.format, "tif");
+        assert_eq!(options.steps, [ProcessParameters::Binarization(BinarizationParameters { threshold: 123, ..Default::default() })]);
+        assert!(parse_args(&args(&["-o", "out", "--help"])).unwrap().is_none());
+    }
+
+    #[test]
+    fn parse_args_rejects_incomplete_command_lines() {
+        for command_line in [
+            &["-p", "Binarization", "a.png"][..],
+            &["-o", "out", "a.png"],
+            &["-o", "out", "-p", "Binarization"],
+            &["-o", "out", "-s", "threshold=1", "-p", "Binarization", "a.png"],
+            &["-o", "out", "-p", "Binarization", "-s", "threshold=-1", "a.png"],
+            &["-o", "out", "-p", "Binarization", "--unknown", "a.png"],
+            &["-o", "out", "-p", "Binarization", "-f", "gif", "a.png"],
+        ] {
+            assert!(parse_args(&args(command_line)).is_err(), "{:?}", command_line);
+        }
+    }
+
+    #[test]
+    fn unsupported_format_fails_before_reading_inputs() {
+        assert_eq!(run(&args(&["-o", "out", "-p", "Binarization", "-f", "gif", "missing.png"])), 2);
+    }
+
+    #[test]
+    fn process_type_suffix_picks_the_variant() {
+        assert_eq!(process_type_by_name("Canny"), Some(ProcessType::Canny));
+        assert_eq!(process_type_by_name("adaptivebinarization:niblack"), Some(ProcessType::AdaptiveBinarization(AdaptiveMethod::Niblack)));
+        assert_eq!(process_type_by_name("RankFilter:Maximum"), Some(ProcessType::RankFilter(RankKind::Maximum)));
+        assert_eq!(process_type_by_name("RankFilter:Niblack"), None);
+        assert_eq!(process_type_by_name("Canny:Sobel"), None);
+        assert_eq!(process_type_by_name("Sharpen"), None);
+    }
+
+    #[test]
+    fn set_parameter_reaches_nested_fields() {
+        let mut step = serde_json::to_value(ProcessType::AdaptiveBinarization(AdaptiveMethod::Mean).default_parameters()).unwrap();
+        set_parameter(&mut step, "parameters.window_size = 31").unwrap();
+        set_parameter(&mut step, "method=Sauvola").unwrap();
+        assert_eq!(step["parameters"]["window_size"], 31);
+        assert_eq!(step["method"], "Sauvola");
+        assert!(set_parameter(&mut step, "parameters.size=31").is_err());
+        assert!(set_parameter(&mut step, "window_size").is_err());
+    }
+
+    #[test]
+    fn output_collisions_are_reported() {
+        let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+        assert!(check_output_paths(&paths(&["a/x.png", "a/y.png"]), &options("png")).is_ok());
+        assert!(check_output_paths(&paths(&["a/x.png", "a/x.png"]), &options("png")).is_ok());
+        assert!(check_output_paths(&paths(&["a/x.png", "b/x.tif"]), &options("png")).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+//! Processing of color images one channel at a time, so that the gray
+//! operations can be used on color photos without discarding their color.
+
+use std::{fmt, hash, sync::Arc};
+
+use image_crate::{DynamicImage, Luma, Rgb, Rgba};
+use serde::{Deserialize, Serialize};
+
+use crate::parallel;
+use crate::process::{
+    process_image, process_pipeline, EdgeOutput, EdgeParameters, ImagePanelData, ImageProcessError, LumaBuffer, ProcessParameters
+};
+use crate::progress::ProcessProgress;
+
+/// D65 reference white of the Lab conversion.
+const WHITE_POINT: [f64; 3] = [0.95047, 1.0, 1.08883];
+const LAB_EPSILON: f64 = 6.0 / 29.0;
+const MAX_LIGHTNESS: f64 = 100.0;
+
+/// Which image a pipeline runs on and how its results are put back together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, hash::Hash, Serialize, Deserialize)]
+pub enum ColorMode {
+    /// Runs on the Grayscale image.
+    #[default]
+    Grayscale,
+    /// Runs on the red, green and blue channels of the Original image and
+    /// merges the results, keeping its alpha.
+    Channels,
+    /// Runs on the Lab lightness of the Original image and keeps its a and b.
+    Lightness,
+}
+impl ColorMode {
+    pub const ALL: &'static [Self] = &[
+        Self::Grayscale,
+        Self::Channels,
+        Self::Lightness,
+    ];
+}
+impl fmt::Display for ColorMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColorMode::Grayscale => write!(f, "Grayscale"),
+            ColorMode::Channels => write!(f, "Per channel (RGB)"),
+            ColorMode::Lightness => write!(f, "Lightness (Lab L)"),
+        }
+    }
+}
+
+/// Like `process_pipeline`, with `image_panel_data` split up as `mode` says.
+/// Every step result is merged back into a color image.
+pub async fn process_color_pipeline(image_panel_data: ImagePanelData, steps: Vec<ProcessParameters>, mode: ColorMode, progress: Arc<ProcessProgress>) -> Result<Vec<ImagePanelData>, ImageProcessError> {
+    if steps.is_empty() {
+        return Err(ImageProcessError { message: String::from("pipeline has no steps") });
+    }
+    // Merging reads every result as gray, which would drop the hue of a direction image.
+    let colored_result = |step: &ProcessParameters| {
+        matches!(step, ProcessParameters::EdgeDetection { parameters: EdgeParameters { output: EdgeOutput::Direction, .. }, .. })
+    };
+    if mode != ColorMode::Grayscale && steps.iter().any(colored_result) {
+        return Err(ImageProcessError { message: String::from("the edge direction output only works in Grayscale mode") });
+    }
+    let image = image_panel_data.get_image();
+    match mode {
+        ColorMode::Grayscale => process_pipeline(image_panel_data, steps, progress).await,
+        ColorMode::Channels => {
+            let rgba_img_buf = image.to_rgba16();
+            // Alpha is not processed, a step that blacks out flat areas would make the image transparent.
+            let alpha: Option<Vec<u16>> = image.color().has_alpha().then(|| rgba_img_buf.pixels().map(|pixel| pixel[3]).collect());
+            let channels: Vec<ImagePanelData> = (0..3)
+                .map(|channel| {
+                    let channel_img_buf = parallel::from_fn(rgba_img_buf.width(), rgba_img_buf.height(), &progress, |x, y| {
+                        Luma([rgba_img_buf.get_pixel(x, y)[channel]])
+                    });
+                    ImagePanelData::new(DynamicImage::ImageLuma16(channel_img_buf))
+                })
+                .collect();
+            let results = process_channels(channels, steps, &progress).await?;
+            let results = results.iter().map(|channels| merge_channels(channels, rgba_img_buf.width(), alpha.as_deref(), &progress)).collect();
+            progress.check()?;
+            Ok(results)
+        },
+        ColorMode::Lightness => {
+            let lab = LabImage::new(&image, &progress);
+            let lightness = ImagePanelData::new(DynamicImage::ImageLuma16(lab.lightness.clone()));
+            let results = process_pipeline(lightness, steps, progress.clone()).await?;
+            let results = results.iter().map(|result| lab.with_lightness(result, &progress)).collect();
+            progress.check()?;
+            Ok(results)
+        },
+    }
+}
+
+/// Runs every step on each of `channels`; the result holds the channels of every step.
+async fn process_channels(channels: Vec<ImagePanelData>, steps: Vec<ProcessParameters>, progress: &ProcessProgress) -> Result<Vec<Vec<ImagePanelData>>, ImageProcessError> {
+    let step_count = steps.len() * channels.len();
+    let mut results: Vec<Vec<ImagePanelData>> = Vec::with_capacity(steps.len());
+    for (index, step) in steps.into_iter().enumerate() {
+        let inputs = results.last().cloned().unwrap_or_else(|| channels.clone());
+        let mut step_results = Vec::with_capacity(inputs.len());
+        for (channel, input) in inputs.into_iter().enumerate() {
+            progress.check()?;
+            progress.begin_step(index * channels.len() + channel, step_count);
+            step_results.push(process_image(input, step, progress).await?);
+        }
+        results.push(step_results);
+    }
+    Ok(results)
+}
+
+/// Puts the red, green and blue results back together, adding `alpha` of the
+/// `input_width` wide input at the part of the image the results cover.
+fn merge_channels(channels: &[ImagePanelData], input_width: u32, alpha: Option<&[u16]>, progress: &ProcessProgress) -> ImagePanelData {
+    let (left, top) = channels[0].get_origin();
+    let channels: Vec<LumaBuffer> = channels.iter().map(|channel| channel.get_image().to_luma16()).collect();
+    let (width, height) = channels[0].dimensions();
+    let value = |channel: usize, x: u32, y: u32| channels[channel].get_pixel(x, y)[0];
+    let image = match alpha {
+        Some(alpha) => DynamicImage::ImageRgba16(parallel::from_fn(width, height, progress, |x, y| {
+            let index = ((y + top) * input_width + x + left) as usize;
+            Rgba([value(0, x, y), value(1, x, y), value(2, x, y), alpha[index]])
+        })),
+        None => DynamicImage::ImageRgb16(parallel::from_fn(width, height, progress, |x, y| {
+            Rgb([value(0, x, y), value(1, x, y), value(2, x, y)])
+        })),
+    };
+    ImagePanelData { origin: (left, top), ..ImagePanelData::new(image) }
+}
+
+/// CIE Lab version of an sRGB image. The lightness is scaled from 0-100 to the
+/// 16-bit range so that the gray operations can run on it.
+struct LabImage {
+    width: u32,
+    lightness: LumaBuffer,
+    a: Vec<f32>,
+    b: Vec<f32>,
+    alpha: Option<Vec<u16>>,
+}
+
+impl LabImage {
+    fn new(image: &DynamicImage, progress: &ProcessProgress) -> Self {
+        let rgba_img_buf = image.to_rgba16();
+        let (width, height) = rgba_img_buf.dimensions();
+        let lab: Vec<[f64; 3]> = rgba_img_buf.pixels()
+            .map(|pixel| srgb_to_lab([pixel[0], pixel[1], pixel[2]]))
+            .collect();
+        let lightness = parallel::from_fn(width, height, progress, |x, y| {
+            let lightness = lab[(y * width + x) as usize][0] / MAX_LIGHTNESS;
+            Luma([(lightness.clamp(0.0, 1.0) * u16::MAX as f64).round() as u16])
+        });
+        LabImage {
+            width,
+            lightness,
+            a: lab.iter().map(|lab| lab[1] as f32).collect(),
+            b: lab.iter().map(|lab| lab[2] as f32).collect(),
+            alpha: image.color().has_alpha().then(|| rgba_img_buf.pixels().map(|pixel| pixel[3]).collect()),
+        }
+    }
+
+    /// Converts back to sRGB with the lightness replaced by that of `result`,
+    /// which covers the part of the image from its origin on.
+    fn with_lightness(&self, result: &ImagePanelData, progress: &ProcessProgress) -> ImagePanelData {
+        let lightness = result.get_image().to_luma16();
+        let (width, height) = lightness.dimensions();
+        let (left, top) = result.get_origin();
+        let rgb = |x: u32, y: u32| {
+            let index = ((y + top) * self.width + x + left) as usize;
+            let lightness = lightness.get_pixel(x, y)[0] as f64 / u16::MAX as f64 * MAX_LIGHTNESS;
+            (index, lab_to_srgb([lightness, self.a[index] as f64, self.b[index] as f64]))
+        };
+        let image = match &self.alpha {
+            Some(alpha) => DynamicImage::ImageRgba16(parallel::from_fn(width, height, progress, |x, y| {
+                let (index, [red, green, blue]) = rgb(x, y);
+                Rgba([red, green, blue, alpha[index]])
+            })),
+            None => DynamicImage::ImageRgb16(parallel::from_fn(width, height, progress, |x, y| Rgb(rgb(x, y).1))),
+        };
+        ImagePanelData { origin: (left, top), ..ImagePanelData::new(image) }
+    }
+}
+
+fn srgb_to_lab(rgb: [u16; 3]) -> [f64; 3] {
+    let [red, green, blue] = rgb.map(|value| {
+        let value = value as f64 / u16::MAX as f64;
+        if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+    });
+    let xyz = [
+        0.4124564 * red + 0.3575761 * green + 0.1804375 * blue,
+        0.2126729 * red + 0.7151522 * green + 0.0721750 * blue,
+        0.0193339 * red + 0.1191920 * green + 0.9503041 * blue,
+    ];
+    let [fx, fy, fz] = [0, 1, 2].map(|i| {
+        let t = xyz[i] / WHITE_POINT[i];
+        if t > LAB_EPSILON.powi(3) { t.cbrt() } else { t / (3.0 * LAB_EPSILON * LAB_EPSILON) + 4.0 / 29.0 }
+    });
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn lab_to_srgb(lab: [f64; 3]) -> [u16; 3] {
+    let fy = (lab[0] + 16.0) / 116.0;
+    let f = [fy + lab[1] / 500.0, fy, fy - lab[2] / 200.0];
+    let [x, y, z] = [0, 1, 2].map(|i| {
+        let t = if f[i] > LAB_EPSILON { f[i].powi(3) } else { 3.0 * LAB_EPSILON * LAB_EPSILON * (f[i] - 4.0 / 29.0) };
+        t * WHITE_POINT[i]
+    });
+    let linear = [
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    ];
+    linear.map(|value| {
+        let value = if value <= 0.0031308 { 12.92 * value } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 };
+        (value.clamp(0.0, 1.0) * u16::MAX as f64).round() as u16
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::{BinarizationParameters, CropParameters, EdgeOperator};
+    use image_crate::ImageBuffer;
+
+    fn run(image: DynamicImage, steps: Vec<ProcessParameters>, mode: ColorMode) -> Result<Vec<ImagePanelData>, ImageProcessError> {
+        pollster::block_on(process_color_pipeline(ImagePanelData::new(image), steps, mode, Arc::new(ProcessProgress::new())))
+    }
+
+    fn color_image(width: u32, height: u32) -> ImageBuffer<Rgb<u16>, Vec<u16>> {
+        ImageBuffer::from_fn(width, height, |x, y| Rgb([(x * 9000) as u16, (y * 13000) as u16, ((x + y) * 5000) as u16]))
+    }
+
+    #[test]
+    fn lab_round_trip_is_within_two_levels() {
+        let levels: Vec<u16> = (0..=15).map(|level| level * 4369).chain([1, 2, 100, 65534]).collect();
+        let mut max_error = 0;
+        for &red in &levels {
+            for &green in &levels {
+                for &blue in &levels {
+                    let rgb = lab_to_srgb(srgb_to_lab([red, green, blue]));
+                    for (before, after) in [red, green, blue].into_iter().zip(rgb) {
+                        max_error = max_error.max(before.abs_diff(after));
+                    }
+                }
+            }
+        }
+        assert!(max_error <= 2, "max error {}", max_error);
+    }
+
+    #[test]
+    fn channels_are_split_and_merged_back_in_place() {
+        let input = color_image(6, 5);
+        let crop = ProcessParameters::Crop(CropParameters { x: 1, y: 2, width: 3, height: 2 });
+        let results = run(DynamicImage::ImageRgb16(input.clone()), vec![ProcessParameters::None, crop], ColorMode::Channels).unwrap();
+        assert_eq!(results[0].get_image().as_rgb16(), Some(&input));
+        assert_eq!(results[1].get_origin(), (1, 2));
+        let cropped = results[1].get_image().to_rgb16();
+        assert_eq!(cropped.dimensions(), (3, 2));
+        for (x, y, pixel) in cropped.enumerate_pixels() {
+            assert_eq!(pixel, input.get_pixel(x + 1, y + 2));
+        }
+    }
+
+    #[test]
+    fn alpha_is_kept_in_color_modes() {
+        let input: ImageBuffer<Rgba<u16>, Vec<u16>> = ImageBuffer::from_fn(6, 5, |x, y| {
+            let Rgb([red, green, blue]) = *color_image(6, 5).get_pixel(x, y);
+            Rgba([red, green, blue, (x * 10000 + y * 1000) as u16])
+        });
+        let steps = vec![
+            ProcessParameters::Crop(CropParameters { x: 2, y: 1, width: 0, height: 0 }),
+            ProcessParameters::Binarization(BinarizationParameters::default()),
+        ];
+        for mode in [ColorMode::Channels, ColorMode::Lightness] {
+            let results = run(DynamicImage::ImageRgba16(input.clone()), steps.clone(), mode).unwrap();
+            let binarized = results[1].get_image().to_rgba16();
+            for (x, y, pixel) in binarized.enumerate_pixels() {
+                assert_eq!(pixel[3], input.get_pixel(x + 2, y + 1)[3], "{:?} ({}, {})", mode, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn direction_output_needs_grayscale_mode() {
+        let direction = ProcessParameters::EdgeDetection {
+            operator: EdgeOperator::Sobel,
+            parameters: EdgeParameters { output: EdgeOutput::Direction, ..Default::default() },
+        };
+        let image = DynamicImage::ImageRgb16(color_image(4, 4));
+        assert!(run(image.clone(), vec![direction], ColorMode::Channels).is_err());
+        assert!(run(image, vec![direction], ColorMode::Grayscale).is_ok());
+    }
+}
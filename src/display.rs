@@ -0,0 +1,31 @@
+//! Conversion of processed images into iced image handles.
+
+use image_crate::{ImageBuffer, Rgba, RgbaImage};
+use iced::widget::image::Handle;
+
+use crate::process::ImagePanelData;
+
+impl ImagePanelData {
+    /// 8-bit version of the image as the panels show it: gray from its 16-bit
+    /// luminance, or in color when it has color channels.
+    pub fn to_display_image(&self) -> RgbaImage {
+        if self.image.color().has_color() {
+            return self.image.to_rgba8();
+        }
+        let binding = self.image.to_luma16();
+        ImageBuffer::from_fn(
+            self.image.width(), 
+            self.image.height(), 
+            |x, y| {
+            let pixel = binding.get_pixel(x, y);
+            Rgba([(pixel[0]/256) as u8, (pixel[0]/256) as u8, (pixel[0]/256) as u8, 255u8])
+        })
+    }
+    pub fn to_handle(&self) -> Handle {
+        Handle::from_pixels(self.image.width(), self.image.height(), self.to_display_image().into_raw())
+    }
+    pub fn to_rgba8_image_handle(&self) -> Handle {
+        let display_img_buf = self.image.to_rgba8();
+        Handle::from_pixels(self.image.width(), self.image.height(), display_img_buf.into_raw())
+    }
+}
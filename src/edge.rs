@@ -0,0 +1,297 @@
+//! Gradient-based edge detection on the 16-bit luminance of an image.
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use image_crate::{DynamicImage, Luma, Rgb};
+
+use crate::filter::{gaussian_blur_luma, luma_filter_panel_data};
+use crate::parallel;
+use crate::process::{BorderMode, CannyParameters, EdgeOperator, EdgeOutput, EdgeParameters, ImagePanelData, ImageProcessError, LumaBuffer};
+use crate::progress::ProcessProgress;
+
+/// Automatic Canny thresholds as fractions of the median of the smoothed image.
+const CANNY_AUTO_LOW: f64 = 0.66;
+const CANNY_AUTO_HIGH: f64 = 1.33;
+
+impl EdgeOperator {
+    /// Weights across the derivative direction; the derivative itself is `[-1, 0, 1]`.
+    fn smoothing(&self) -> [f64; 3] {
+        match self {
+            EdgeOperator::Sobel => [1.0, 2.0, 1.0],
+            EdgeOperator::Prewitt => [1.0, 1.0, 1.0],
+            EdgeOperator::Scharr => [3.0, 10.0, 3.0],
+        }
+    }
+
+    /// Largest gradient magnitude a 16-bit image can give.
+    fn max_magnitude(&self) -> f64 {
+        self.smoothing().iter().sum::<f64>() * u16::MAX as f64 * 2f64.sqrt()
+    }
+}
+
+/// Horizontal and vertical derivative at every pixel, row by row.
+pub(crate) fn gradient(luma: &LumaBuffer, operator: EdgeOperator, border: BorderMode, progress: &ProcessProgress) -> Vec<(f64, f64)> {
+    let (width, height) = (luma.width() as usize, luma.height() as usize);
+    let smoothing = operator.smoothing();
+    let value = |x: i64, y: i64| {
+        border.sample(luma, x, y)
+            .or_else(|| BorderMode::Replicate.sample(luma, x, y))
+            .unwrap_or(0) as f64
+    };
+    let mut gradient = vec![(0.0, 0.0); width * height];
+    parallel::for_each_row(&mut gradient, width, progress, |y, row| {
+        let y = y as i64;
+        for (x, output) in row.iter_mut().enumerate() {
+            let x = x as i64;
+            let (mut dx, mut dy) = (0.0, 0.0);
+            for (offset, weight) in (-1..=1).zip(smoothing) {
+                dx += weight * (value(x + 1, y + offset) - value(x - 1, y + offset));
+                dy += weight * (value(x + offset, y + 1) - value(x + offset, y - 1));
+            }
+            *output = (dx, dy);
+        }
+    });
+    gradient
+}
+
+/// Value `percent` percent of the way through the sorted `values`.
+fn percentile(mut values: Vec<f64>, percent: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let index = ((percent.clamp(0.0, 100.0) / 100.0) * (values.len() - 1) as f64).round() as usize;
+    *values.select_nth_unstable_by(index, |a, b| a.total_cmp(b)).1
+}
+
+/// Factor that maps a magnitude to the 0-1 range before clamping. Flat areas
+/// are left out of the percentile so that sparse edges still set the scale.
+fn magnitude_scale(magnitudes: &[f64], operator: EdgeOperator, parameters: EdgeParameters) -> f64 {
+    let white = match parameters.normalize {
+        true => {
+            let edges = magnitudes.iter().copied().filter(|magnitude| *magnitude > 0.0).collect();
+            percentile(edges, parameters.clip_percentile)
+        },
+        false => operator.max_magnitude(),
+    };
+    if white > 0.0 { 1.0 / white } else { 0.0 }
+}
+
+/// Fully saturated color of `hue` (0-1) at brightness `value` (0-1).
+fn hsv_to_rgb(hue: f64, value: f64) -> [u16; 3] {
+    let sector = (hue.rem_euclid(1.0) * 6.0).min(5.999_999);
+    let fraction = sector.fract();
+    let (rising, falling) = (value * fraction, value * (1.0 - fraction));
+    let [red, green, blue] = match sector as u32 {
+        0 => [value, rising, 0.0],
+        1 => [falling, value, 0.0],
+        2 => [0.0, value, rising],
+        3 => [0.0, falling, value],
+        4 => [rising, 0.0, value],
+        _ => [value, 0.0, falling],
+    };
+    [red, green, blue].map(|channel| (channel * u16::MAX as f64).round() as u16)
+}
+
+pub(crate) async fn edge_image(dynamic_img: Arc<DynamicImage>, operator: EdgeOperator, parameters: EdgeParameters, progress: &ProcessProgress) -> Result<ImagePanelData, ImageProcessError> {
+    if !(0.0..=100.0).contains(&parameters.clip_percentile) {
+        return Err(ImageProcessError { message: String::from("clip percentile must be between 0 and 100") });
+    }
+    match parameters.output {
+        EdgeOutput::Magnitude => {
+            progress.expect_passes(3);
+            luma_filter_panel_data(dynamic_img, progress, |luma| {
+                let gradient = gradient(luma, operator, parameters.border, progress);
+                let magnitudes: Vec<f64> = gradient.iter().map(|(dx, dy)| dx.hypot(*dy)).collect();
+                let scale = magnitude_scale(&magnitudes, operator, parameters);
+                let width = luma.width() as usize;
+                parallel::from_fn(luma.width(), luma.height(), progress, |x, y| {
+                    let magnitude = magnitudes[y as usize * width + x as usize] * scale;
+                    Luma([(magnitude.min(1.0) * u16::MAX as f64).round() as u16])
+                })
+            })
+        },
+        EdgeOutput::Direction => {
+            progress.expect_passes(2);
+            let luma = dynamic_img.to_luma16();
+            let gradient = gradient(&luma, operator, parameters.border, progress);
+            let magnitudes: Vec<f64> = gradient.iter().map(|(dx, dy)| dx.hypot(*dy)).collect();
+            let scale = magnitude_scale(&magnitudes, operator, parameters);
+            let width = luma.width() as usize;
+            let direction_img_buf = parallel::from_fn(luma.width(), luma.height(), progress, |x, y| {
+                let index = y as usize * width + x as usize;
+                let (dx, dy) = gradient[index];
+                // Image y points down, so the angle is measured clockwise from the x axis.
+                let hue = dy.atan2(dx) / (2.0 * PI);
+                Rgb(hsv_to_rgb(hue, (magnitudes[index] * scale).min(1.0)))
+            });
+            progress.check()?;
+            Ok(ImagePanelData::new(DynamicImage::ImageRgb16(direction_img_buf)))
+        },
+    }
+}
+
+fn median(luma: &LumaBuffer) -> f64 {
+    let mut histogram = vec![0usize; u16::MAX as usize + 1];
+    for value in luma.iter() {
+        histogram[*value as usize] += 1;
+    }
+    let half = luma.len().div_ceil(2);
+    let mut seen = 0;
+    for (value, count) in histogram.iter().enumerate() {
+        seen += count;
+        if seen >= half {
+            return value as f64;
+        }
+    }
+    0.0
+}
+
+/// Keeps the magnitudes that are the largest of their neighbors along the
+/// gradient direction, rounded to one of four directions; the rest become 0.
+fn non_maximum_suppression(gradient: &[(f64, f64)], width: usize, height: usize, progress: &ProcessProgress) -> Vec<f64> {
+    let magnitude = |x: i64, y: i64| {
+        if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+            return 0.0;
+        }
+        let (dx, dy) = gradient[y as usize * width + x as usize];
+        dx.hypot(dy)
+    };
+    let mut suppressed = vec![0f64; width * height];
+    parallel::for_each_row(&mut suppressed, width, progress, |y, row| {
+        let y = y as i64;
+        for (x, output) in row.iter_mut().enumerate() {
+            let x = x as i64;
+            let (dx, dy) = gradient[y as usize * width + x as usize];
+            let center = dx.hypot(dy);
+            if center == 0.0 {
+                continue;
+            }
+            // Angle folded into 0-180 degrees, then rounded to 0, 45, 90 or 135.
+            let angle = dy.atan2(dx).to_degrees().rem_euclid(180.0);
+            let (step_x, step_y) = match angle {
+                angle if !(22.5..157.5).contains(&angle) => (1, 0),
+                angle if angle < 67.5 => (1, 1),
+                angle if angle < 112.5 => (0, 1),
+                _ => (-1, 1),
+            };
+            // Ties go to the first pixel along the direction, so plateaus stay one pixel wide.
+            if center > magnitude(x - step_x, y - step_y) && center >= magnitude(x + step_x, y + step_y) {
+                *output = center;
+            }
+        }
+    });
+    suppressed
+}
+
+/// Marks the pixels at or above `high` and those at or above `low` that
+/// connect to them through their 8 neighbors.
+fn hysteresis(magnitudes: &[f64], width: usize, low: f64, high: f64) -> Vec<bool> {
+    let height = magnitudes.len().checked_div(width).unwrap_or(0);
+    let mut edges = vec![false; magnitudes.len()];
+    let mut stack: Vec<usize> = Vec::new();
+    for (index, magnitude) in magnitudes.iter().enumerate() {
+        if *magnitude >= high && *magnitude > 0.0 {
+            edges[index] = true;
+            stack.push(index);
+        }
+    }
+    while let Some(index) = stack.pop() {
+        let (x, y) = ((index % width) as i64, (index / width) as i64);
+        for (offset_x, offset_y) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+            let (neighbor_x, neighbor_y) = (x + offset_x, y + offset_y);
+            if neighbor_x < 0 || neighbor_y < 0 || neighbor_x >= width as i64 || neighbor_y >= height as i64 {
+                continue;
+            }
+            let neighbor = neighbor_y as usize * width + neighbor_x as usize;
+            if !edges[neighbor] && magnitudes[neighbor] >= low && magnitudes[neighbor] > 0.0 {
+                edges[neighbor] = true;
+                stack.push(neighbor);
+            }
+        }
+    }
+    edges
+}
+
+/// Thin edges: Gaussian smoothing, Sobel gradient, non-maximum suppression and
+/// hysteresis between the two thresholds.
+pub(crate) async fn canny_image(dynamic_img: Arc<DynamicImage>, parameters: CannyParameters, progress: &ProcessProgress) -> Result<ImagePanelData, ImageProcessError> {
+    if parameters.sigma < 0.0 {
+        return Err(ImageProcessError { message: String::from("sigma must not be negative") });
+    }
+    if !parameters.automatic && parameters.low_threshold > parameters.high_threshold {
+        return Err(ImageProcessError { message: String::from("low threshold must not exceed the high threshold") });
+    }
+    let smoothing = parameters.sigma > 0.0;
+    progress.expect_passes(if smoothing { 7 } else { 4 });
+    luma_filter_panel_data(dynamic_img, progress, |luma| {
+        let smoothed = match smoothing {
+            true => gaussian_blur_luma(luma, parameters.sigma, 0, parameters.border, progress),
+            false => luma.clone(),
+        };
+        let (width, height) = (luma.width() as usize, luma.height() as usize);
+        let operator = EdgeOperator::Sobel;
+        let gradient = gradient(&smoothed, operator, parameters.border, progress);
+        let suppressed = non_maximum_suppression(&gradient, width, height, progress);
+        // Automatic thresholds apply to the raw Sobel magnitude, as in the usual
+        // median rule; the manual ones are step contrasts in gray levels.
+        let (low, high) = match parameters.automatic {
+            true => {
+                let median = median(&smoothed);
+                (CANNY_AUTO_LOW * median, CANNY_AUTO_HIGH * median)
+            },
+            false => {
+                let gray_levels = operator.smoothing().iter().sum::<f64>();
+                (parameters.low_threshold as f64 * gray_levels, parameters.high_threshold as f64 * gray_levels)
+            },
+        };
+        let edges = hysteresis(&suppressed, width, low, high);
+        parallel::from_fn(luma.width(), luma.height(), progress, |x, y| {
+            match edges[y as usize * width + x as usize] {
+                true => Luma([u16::MAX]),
+                false => Luma([0]),
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image_crate::ImageBuffer;
+
+    #[test]
+    fn step_edge_is_one_pixel_wide() {
+        let (width, height) = (8, 6);
+        let luma: LumaBuffer = ImageBuffer::from_fn(width, height, |x, _| Luma([if x < 4 { 0 } else { u16::MAX }]));
+        let progress = ProcessProgress::new();
+        let gradient = gradient(&luma, EdgeOperator::Sobel, BorderMode::Replicate, &progress);
+        let suppressed = non_maximum_suppression(&gradient, width as usize, height as usize, &progress);
+        for row in suppressed.chunks(width as usize) {
+            let kept: Vec<usize> = (0..row.len()).filter(|x| row[*x] > 0.0).collect();
+            assert_eq!(kept, [3]);
+        }
+    }
+
+    #[test]
+    fn weak_pixels_need_a_strong_neighbor() {
+        let magnitudes = [
+            9.0, 0.0, 0.0, 0.0, 0.0,
+            0.0, 5.0, 5.0, 0.0, 0.0,
+            0.0, 0.0, 0.0, 0.0, 5.0,
+        ];
+        let edges = hysteresis(&magnitudes, 5, 4.0, 8.0);
+        let marked: Vec<usize> = (0..edges.len()).filter(|index| edges[*index]).collect();
+        assert_eq!(marked, [0, 6, 7]);
+        assert!(hysteresis(&magnitudes, 5, 4.0, 10.0).iter().all(|edge| !edge));
+    }
+
+    #[test]
+    fn low_threshold_above_high_is_rejected() {
+        let image = Arc::new(DynamicImage::new_luma16(4, 4));
+        let parameters = CannyParameters { automatic: false, low_threshold: 2000, high_threshold: 1000, ..CannyParameters::default() };
+        assert!(pollster::block_on(canny_image(image.clone(), parameters, &ProcessProgress::new())).is_err());
+        let automatic = CannyParameters { automatic: true, ..parameters };
+        assert!(pollster::block_on(canny_image(image, automatic, &ProcessProgress::new())).is_ok());
+    }
+}
@@ -0,0 +1,333 @@
+//! Smoothing filters that work on the 16-bit luminance of an image.
+
+use std::sync::Arc;
+
+use image_crate::{DynamicImage, ImageBuffer, Luma};
+
+use crate::integral::IntegralImage;
+use crate::parallel;
+use crate::progress::ProcessProgress;
+use crate::process::{
+    check_window_size, create_image_panel_data, max_window_size, BorderMode, BorderSample, FilterParameters, GaussianParameters,
+    ImagePanelData, ImageProcessError, LumaBuffer, RankKind, RankParameters, WindowShape
+};
+
+/// Runs `f` on the 16-bit luminance of `dynamic_img` and keeps its result as is.
+pub(crate) fn luma_filter_panel_data<F>(dynamic_img: Arc<DynamicImage>, progress: &ProcessProgress, f: F) -> Result<ImagePanelData, ImageProcessError>
+where
+F: Fn(&LumaBuffer) -> LumaBuffer,
+{
+    create_image_panel_data(
+        dynamic_img,
+        progress,
+        |dynamic_img: Arc<DynamicImage>| Arc::new(f(&dynamic_img.to_luma16())),
+        |x, y, image_buf| *image_buf.get_pixel(x, y))
+}
+
+/// Mean of the `window_size` x `window_size` window around each pixel, read
+/// from an integral image so the cost per pixel does not depend on the size.
+pub fn box_filter(luma: &LumaBuffer, window_size: u32, border: BorderMode) -> LumaBuffer {
+    box_filter_with_progress(luma, window_size, border, &ProcessProgress::new())
+}
+
+fn box_filter_with_progress(luma: &LumaBuffer, window_size: u32, border: BorderMode, progress: &ProcessProgress) -> LumaBuffer {
+    let radius = window_size / 2;
+    let integral = IntegralImage::new(luma, radius, border);
+    parallel::from_fn(luma.width(), luma.height(), progress, |x, y| {
+        let statistics = integral.window(x, y, radius);
+        Luma([((statistics.sum + statistics.count / 2) / statistics.count) as u16])
+    })
+}
+
+pub(crate) async fn box_filter_image(dynamic_img: Arc<DynamicImage>, parameters: FilterParameters, progress: &ProcessProgress) -> Result<ImagePanelData, ImageProcessError> {
+    if parameters.filter_size == 0 {
+        return Err(ImageProcessError { message: String::from("filter size must be at least 1") });
+    }
+    check_window_size("filter size", (parameters.filter_size | 1) as u64, dynamic_img.width(), dynamic_img.height())?;
+    progress.expect_passes(2);
+    luma_filter_panel_data(dynamic_img, progress, |luma| {
+        box_filter_with_progress(luma, parameters.filter_size | 1, parameters.border, progress)
+    })
+}
+
+/// Normalized 1-D Gaussian kernel of `kernel_size` taps.
+pub(crate) fn gaussian_kernel(sigma: f64, kernel_size: usize) -> Vec<f64> {
+    let radius = (kernel_size / 2) as i64;
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|i| (-((i * i) as f64) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f64 = kernel.iter().sum();
+    kernel.into_iter().map(|weight| weight / sum).collect()
+}
+
+/// Convolves every row (`horizontal`) or column of `src` with `kernel`. Taps
+/// dropped by `border` are left out and the remaining weights renormalized.
+fn convolve_1d(src: &[f64], width: usize, height: usize, kernel: &[f64], horizontal: bool, border: BorderMode, progress: &ProcessProgress) -> Vec<f64> {
+    let radius = (kernel.len() / 2) as i64;
+    let mut dst = vec![0f64; src.len()];
+    parallel::for_each_row(&mut dst, width, progress, |y, row| {
+        for (x, output) in row.iter_mut().enumerate() {
+            let (position, length) = if horizontal { (x as i64, width as i64) } else { (y as i64, height as i64) };
+            let mut weight_sum = 0f64;
+            let mut value_sum = 0f64;
+            for (k, weight) in kernel.iter().enumerate() {
+                let value = match border.locate(position + k as i64 - radius, length) {
+                    BorderSample::Inside(sample) if horizontal => src[y * width + sample as usize],
+                    BorderSample::Inside(sample) => src[sample as usize * width + x],
+                    BorderSample::Constant(value) => value as f64,
+                    BorderSample::Skip => continue,
+                };
+                weight_sum += weight;
+                value_sum += weight * value;
+            }
+            *output = value_sum / weight_sum;
+        }
+    });
+    dst
+}
+
+/// Convolves the rows and then the columns of `luma` with `kernel`.
+pub(crate) fn separable_convolve(luma: &LumaBuffer, kernel: &[f64], border: BorderMode, progress: &ProcessProgress) -> Vec<f64> {
+    let (width, height) = (luma.width() as usize, luma.height() as usize);
+    let values: Vec<f64> = luma.iter().map(|value| *value as f64).collect();
+    let rows = convolve_1d(&values, width, height, kernel, true, border, progress);
+    convolve_1d(&rows, width, height, kernel, false, border, progress)
+}
+
+/// `luma` blurred with a Gaussian of `sigma` and rounded back to 16 bits. A
+/// `kernel_size` of 0 reaches three sigmas to each side, within `max_window_size`.
+pub(crate) fn gaussian_blur_luma(luma: &LumaBuffer, sigma: f64, kernel_size: u32, border: BorderMode, progress: &ProcessProgress) -> LumaBuffer {
+    let kernel_size = match kernel_size {
+        0 => (2.0 * (3.0 * sigma).ceil() + 1.0).min(max_window_size(luma.width(), luma.height()) as f64) as usize,
+        kernel_size => (kernel_size | 1) as usize,
+    };
+    let kernel = gaussian_kernel(sigma, kernel_size);
+    let width = luma.width() as usize;
+    let blurred = separable_convolve(luma, &kernel, border, progress);
+    parallel::from_fn(luma.width(), luma.height(), progress, |x, y| {
+        Luma([blurred[y as usize * width + x as usize].round() as u16])
+    })
+}
+
+pub(crate) async fn gaussian_blur(dynamic_img: Arc<DynamicImage>, parameters: GaussianParameters, progress: &ProcessProgress) -> Result<ImagePanelData, ImageProcessError> {
+    if parameters.sigma <= 0.0 {
+        return Err(ImageProcessError { message: String::from("sigma must be greater than 0") });
+    }
+    check_window_size("kernel size", (parameters.kernel_size | 1) as u64, dynamic_img.width(), dynamic_img.height())?;
+    progress.expect_passes(4);
+    luma_filter_panel_data(dynamic_img, progress, |luma| {
+        gaussian_blur_luma(luma, parameters.sigma, parameters.kernel_size, parameters.border, progress)
+    })
+}
+
+/// Histogram of the 16-bit values inside a sliding window. The fine bins are
+/// grouped into 256 coarse bins so that a rank lookup scans at most 512 bins.
+struct SlidingHistogram {
+    coarse: Vec<u32>,
+    fine: Vec<u32>,
+    count: u32,
+}
+
+impl SlidingHistogram {
+    fn new() -> Self {
+        SlidingHistogram {
+            coarse: vec![0; 256],
+            fine: vec![0; u16::MAX as usize + 1],
+            count: 0,
+        }
+    }
+    fn add(&mut self, value: u16) {
+        self.coarse[(value >> 8) as usize] += 1;
+        self.fine[value as usize] += 1;
+        self.count += 1;
+    }
+    fn remove(&mut self, value: u16) {
+        self.coarse[(value >> 8) as usize] -= 1;
+        self.fine[value as usize] -= 1;
+        self.count -= 1;
+    }
+    /// Value at zero-based position `rank` of the sorted window contents.
+    fn value_at_rank(&self, rank: u32) -> u16 {
+        let mut seen = 0;
+        for (coarse_index, coarse_count) in self.coarse.iter().enumerate() {
+            if seen + coarse_count <= rank {
+                seen += coarse_count;
+                continue;
+            }
+            let start = coarse_index << 8;
+            for (fine_index, fine_count) in self.fine[start..start + 256].iter().enumerate() {
+                seen += fine_count;
+                if seen > rank {
+                    return (start + fine_index) as u16;
+                }
+            }
+        }
+        u16::MAX
+    }
+}
+
+/// Replaces each pixel by the value at `percentile` (0-100) of its neighborhood.
+/// The window slides along each row, so every step only adds and removes the
+/// pixels on its left and right edges.
+fn rank_filter(luma: &LumaBuffer, window_size: u32, shape: WindowShape, percentile: f64, border: BorderMode, progress: &ProcessProgress) -> LumaBuffer {
+    let (width, height) = (luma.width() as i64, luma.height() as i64);
+    let radius = (window_size / 2) as i64;
+    // Row offset from the center paired with the half width of the window on that row.
+    let rows: Vec<(i64, i64)> = (-radius..=radius)
+        .map(|dy| match shape {
+            WindowShape::Square => (dy, radius),
+            WindowShape::Circle => (dy, ((radius * radius - dy * dy) as f64).sqrt().floor() as i64),
+        })
+        .collect();
+
+    let mut output = vec![0u16; (width * height) as usize];
+    parallel::for_each_row_init(&mut output, width as usize, progress, SlidingHistogram::new, |histogram, y, row| {
+        let y = y as i64;
+        for &(dy, half_width) in &rows {
+            for x in -half_width..=half_width {
+                if let Some(value) = border.sample(luma, x, y + dy) {
+                    histogram.add(value);
+                }
+            }
+        }
+        for x in 0..width {
+            let rank = (percentile / 100.0 * (histogram.count - 1) as f64).round() as u32;
+            row[x as usize] = histogram.value_at_rank(rank);
+            if x + 1 == width {
+                break;
+            }
+            for &(dy, half_width) in &rows {
+                if let Some(value) = border.sample(luma, x - half_width, y + dy) {
+                    histogram.remove(value);
+                }
+                if let Some(value) = border.sample(luma, x + half_width + 1, y + dy) {
+                    histogram.add(value);
+                }
+            }
+        }
+        for &(dy, half_width) in &rows {
+            for x in width - 1 - half_width..=width - 1 + half_width {
+                if let Some(value) = border.sample(luma, x, y + dy) {
+                    histogram.remove(value);
+                }
+            }
+        }
+    });
+    ImageBuffer::from_raw(width as u32, height as u32, output).unwrap()
+}
+
+pub(crate) async fn rank_filter_image(dynamic_img: Arc<DynamicImage>, rank: RankKind, parameters: RankParameters, progress: &ProcessProgress) -> Result<ImagePanelData, ImageProcessError> {
+    if parameters.window_size == 0 {
+        return Err(ImageProcessError { message: String::from("window size must be at least 1") });
+    }
+    check_window_size("window size", (parameters.window_size | 1) as u64, dynamic_img.width(), dynamic_img.height())?;
+    let percentile = match rank {
+        RankKind::Median => 50.0,
+        RankKind::Minimum => 0.0,
+        RankKind::Maximum => 100.0,
+        RankKind::Percentile => parameters.percentile.clamp(0.0, 100.0),
+    };
+    progress.expect_passes(2);
+    luma_filter_panel_data(dynamic_img, progress, |luma| {
+        rank_filter(luma, parameters.window_size | 1, parameters.shape, percentile, parameters.border, progress)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{naive_sample, test_image, BORDERS};
+
+    /// Values of the window around (x, y), the circle being the offsets within `radius`.
+    fn naive_window(luma: &LumaBuffer, border: BorderMode, shape: WindowShape, x: u32, y: u32, radius: i64) -> Vec<u16> {
+        let mut values = Vec::new();
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                if shape == WindowShape::Circle && dx * dx + dy * dy > radius * radius {
+                    continue;
+                }
+                values.extend(naive_sample(luma, border, x as i64 + dx, y as i64 + dy));
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn box_filter_matches_naive_mean_in_every_border_mode() {
+        let luma = test_image(7, 5);
+        for border in BORDERS {
+            for window_size in [1, 3, 5, 13] {
+                let filtered = box_filter(&luma, window_size, border);
+                for (x, y, pixel) in filtered.enumerate_pixels() {
+                    let values = naive_window(&luma, border, WindowShape::Square, x, y, window_size as i64 / 2);
+                    let (count, sum) = (values.len() as u64, values.iter().map(|value| *value as u64).sum::<u64>());
+                    assert_eq!(pixel[0] as u64, (sum + count / 2) / count, "{:?}, window {} at ({}, {})", border, window_size, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rank_filter_matches_sorted_window() {
+        let luma = test_image(9, 6);
+        for border in BORDERS {
+            for shape in WindowShape::ALL {
+                for window_size in [1, 3, 7] {
+                    for percentile in [0.0, 30.0, 50.0, 100.0] {
+                        let filtered = rank_filter(&luma, window_size, shape, percentile, border, &ProcessProgress::new());
+                        for (x, y, pixel) in filtered.enumerate_pixels() {
+                            let mut values = naive_window(&luma, border, shape, x, y, window_size as i64 / 2);
+                            values.sort_unstable();
+                            let rank = (percentile / 100.0 * (values.len() - 1) as f64).round() as usize;
+                            assert_eq!(pixel[0], values[rank],
+                                "{:?}, {:?} window {}, percentile {} at ({}, {})", border, shape, window_size, percentile, x, y);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn separable_convolve_matches_naive_weighted_mean() {
+        let luma = test_image(7, 5);
+        let kernel = gaussian_kernel(1.2, 5);
+        for border in BORDERS {
+            let convolved = separable_convolve(&luma, &kernel, border, &ProcessProgress::new());
+            for (x, y, _) in luma.enumerate_pixels() {
+                let (mut weight_sum, mut value_sum) = (0.0, 0.0);
+                for (j, weight_y) in kernel.iter().enumerate() {
+                    for (i, weight_x) in kernel.iter().enumerate() {
+                        let (dx, dy) = (i as i64 - 2, j as i64 - 2);
+                        if let Some(value) = naive_sample(&luma, border, x as i64 + dx, y as i64 + dy) {
+                            weight_sum += weight_x * weight_y;
+                            value_sum += weight_x * weight_y * value as f64;
+                        }
+                    }
+                }
+                let actual = convolved[(y * luma.width() + x) as usize];
+                assert!((actual - value_sum / weight_sum).abs() < 1e-6, "{:?} at ({}, {}): {}", border, x, y, actual);
+            }
+        }
+    }
+
+    #[test]
+    fn gaussian_kernel_is_normalized_and_symmetric() {
+        let kernel = gaussian_kernel(2.0, 9);
+        assert_eq!(kernel.len(), 9);
+        assert!((kernel.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        for i in 0..4 {
+            assert_eq!(kernel[i], kernel[8 - i]);
+            assert!(kernel[i] < kernel[i + 1]);
+        }
+    }
+
+    #[test]
+    fn automatic_gaussian_kernel_stops_at_the_image() {
+        // A kernel of 2 * 3e9 + 1 taps would not fit in memory; flat weights over the whole image remain.
+        let luma = test_image(3, 2);
+        let blurred = gaussian_blur_luma(&luma, 1e9, 0, BorderMode::NormalizeValid, &ProcessProgress::new());
+        let mean = (luma.iter().map(|value| *value as f64).sum::<f64>() / 6.0).round() as u16;
+        assert!(blurred.iter().all(|value| *value == mean));
+    }
+}
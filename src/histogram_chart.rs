@@ -0,0 +1,151 @@
+//! Histogram drawn under each image panel, with markers for the threshold and
+//! the minimum and maximum values.
+
+use iced::advanced::layout::{self, Layout};
+use iced::advanced::renderer::{self, Quad, Renderer as _};
+use iced::advanced::widget::Tree;
+use iced::advanced::Widget;
+use iced::mouse;
+use iced::{Color, Element, Length, Rectangle, Renderer};
+use simple_image_app::statistics::ImageStatistics;
+
+const HISTOGRAM_BINS: usize = 256;
+const CHANNEL_COLORS: [Color; 3] = [
+    Color { r: 0.9, g: 0.2, b: 0.2, a: 0.5 },
+    Color { r: 0.2, g: 0.8, b: 0.2, a: 0.5 },
+    Color { r: 0.2, g: 0.4, b: 0.9, a: 0.5 },
+];
+const LUMA_COLOR: Color = Color { r: 0.3, g: 0.3, b: 0.3, a: 1.0 };
+const THRESHOLD_COLOR: Color = Color { r: 1.0, g: 0.5, b: 0.0, a: 1.0 };
+const MIN_MAX_COLOR: Color = Color { r: 0.6, g: 0.0, b: 0.8, a: 1.0 };
+
+/// Histogram of one panel image, computed once when the image changes.
+#[derive(Debug, Clone)]
+pub struct PanelHistogram {
+    channels: Vec<(Vec<u64>, Color)>,
+    min_value: usize,
+    max_value: usize,
+    threshold: Option<u16>,
+}
+
+impl PanelHistogram {
+    /// Uses one histogram per color channel when `per_channel` is set and the
+    /// image has color, the 16-bit luminance otherwise.
+    pub fn new(statistics: &ImageStatistics, threshold: Option<u16>, per_channel: bool) -> Self {
+        let channels = if per_channel && !statistics.channels.is_empty() {
+            statistics.channels.iter()
+                .zip(CHANNEL_COLORS)
+                .map(|(channel, color)| (channel.histogram(HISTOGRAM_BINS), color))
+                .collect()
+        } else {
+            vec![(statistics.luma.histogram(HISTOGRAM_BINS), LUMA_COLOR)]
+        };
+        PanelHistogram {
+            channels,
+            min_value: statistics.luma.min() as usize,
+            max_value: statistics.luma.max() as usize,
+            threshold,
+        }
+    }
+}
+
+pub struct HistogramChart {
+    histogram: PanelHistogram,
+    log_scale: bool,
+    height: Length,
+}
+
+impl HistogramChart {
+    pub fn new(histogram: &PanelHistogram, log_scale: bool) -> Self {
+        HistogramChart {
+            histogram: histogram.clone(),
+            log_scale,
+            height: Length::Fixed(80.0),
+        }
+    }
+
+    fn bar_height(&self, count: u64, max_count: u64) -> f32 {
+        if max_count == 0 {
+            return 0.0;
+        }
+        if self.log_scale {
+            ((count as f64).ln_1p() / (max_count as f64).ln_1p()) as f32
+        } else {
+            count as f32 / max_count as f32
+        }
+    }
+}
+
+impl<Message> Widget<Message, Renderer> for HistogramChart {
+    fn width(&self) -> Length {
+        Length::Fill
+    }
+
+    fn height(&self) -> Length {
+        self.height
+    }
+
+    fn layout(&self, _renderer: &Renderer, limits: &layout::Limits) -> layout::Node {
+        layout::Node::new(limits.width(Length::Fill).height(self.height).max())
+    }
+
+    fn draw(
+        &self,
+        _tree: &Tree,
+        renderer: &mut Renderer,
+        _theme: &iced::Theme,
+        _style: &renderer::Style,
+        layout: Layout<'_>,
+        _cursor: mouse::Cursor,
+        _viewport: &Rectangle,
+    ) {
+        let bounds = layout.bounds();
+        let fill = |renderer: &mut Renderer, bounds: Rectangle, color: Color| {
+            let quad = Quad {
+                bounds,
+                border_radius: 0.0.into(),
+                border_width: 0.0,
+                border_color: Color::TRANSPARENT,
+            };
+            renderer.fill_quad(quad, color);
+        };
+        let bar_width = bounds.width / HISTOGRAM_BINS as f32;
+        let max_count = self.histogram.channels.iter()
+            .flat_map(|(counts, _)| counts.iter().copied())
+            .max()
+            .unwrap_or(0);
+        for (counts, color) in &self.histogram.channels {
+            for (bin, count) in counts.iter().enumerate() {
+                let height = self.bar_height(*count, max_count) * bounds.height;
+                if height > 0.0 {
+                    let bar = Rectangle {
+                        x: bounds.x + bin as f32 * bar_width,
+                        y: bounds.y + bounds.height - height,
+                        width: bar_width,
+                        height,
+                    };
+                    fill(renderer, bar, *color);
+                }
+            }
+        }
+
+        // Markers are placed by their 16-bit value.
+        let marker = |value: usize| Rectangle {
+            x: bounds.x + value as f32 / (u16::MAX as f32 + 1.0) * bounds.width,
+            y: bounds.y,
+            width: 1.0,
+            height: bounds.height,
+        };
+        fill(renderer, marker(self.histogram.min_value), MIN_MAX_COLOR);
+        fill(renderer, marker(self.histogram.max_value), MIN_MAX_COLOR);
+        if let Some(threshold) = self.histogram.threshold {
+            fill(renderer, marker(threshold as usize), THRESHOLD_COLOR);
+        }
+    }
+}
+
+impl<'a, Message: 'a> From<HistogramChart> for Element<'a, Message> {
+    fn from(histogram_chart: HistogramChart) -> Self {
+        Element::new(histogram_chart)
+    }
+}
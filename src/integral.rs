@@ -0,0 +1,167 @@
+//! Summed-area tables for constant-time window sums.
+
+use crate::process::{BorderMode, LumaBuffer};
+
+/// Sum, sum of squares and pixel count of a rectangular window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowStatistics {
+    pub count: u64,
+    pub sum: u64,
+    pub square_sum: u64,
+}
+
+impl WindowStatistics {
+    pub fn mean(&self) -> f64 {
+        self.sum as f64 / self.count as f64
+    }
+    pub fn variance(&self) -> f64 {
+        let mean = self.mean();
+        (self.square_sum as f64 / self.count as f64 - mean * mean).max(0.0)
+    }
+}
+
+/// Integral image of a 16-bit luminance buffer. Any window sum costs four
+/// lookups, independent of the window size.
+///
+/// The table covers the image plus `padding` pixels on every side, filled in
+/// according to the border mode, so windows reaching up to `padding` pixels
+/// past the edge are still summed correctly. With `BorderMode::NormalizeValid`
+/// there is no padding and windows are clipped to the image instead.
+#[derive(Debug, Clone)]
+pub struct IntegralImage {
+    width: i64,
+    height: i64,
+    padding: i64,
+    stride: usize,
+    sums: Vec<u64>,
+    square_sums: Option<Vec<u64>>,
+}
+
+impl IntegralImage {
+    /// Builds the table of sums only.
+    pub fn new(luma: &LumaBuffer, padding: u32, border: BorderMode) -> Self {
+        Self::build(luma, padding, border, false)
+    }
+
+    /// Builds the tables of sums and of squared values, for local variances.
+    pub fn with_squares(luma: &LumaBuffer, padding: u32, border: BorderMode) -> Self {
+        Self::build(luma, padding, border, true)
+    }
+
+    fn build(luma: &LumaBuffer, padding: u32, border: BorderMode, squares: bool) -> Self {
+        let padding = if border == BorderMode::NormalizeValid { 0 } else { padding as i64 };
+        let (width, height) = (luma.width() as i64, luma.height() as i64);
+        let padded_width = (width + 2 * padding) as usize;
+        let padded_height = (height + 2 * padding) as usize;
+        let stride = padded_width + 1;
+
+        let mut sums = vec![0u64; stride * (padded_height + 1)];
+        let mut square_sums = squares.then(|| vec![0u64; stride * (padded_height + 1)]);
+        for row in 0..padded_height {
+            let mut row_sum = 0u64;
+            let mut row_square_sum = 0u64;
+            for column in 0..padded_width {
+                let x = column as i64 - padding;
+                let y = row as i64 - padding;
+                let value = border.sample(luma, x, y).unwrap_or(0) as u64;
+                row_sum += value;
+                row_square_sum += value * value;
+                let index = (row + 1) * stride + column + 1;
+                sums[index] = sums[index - stride] + row_sum;
+                if let Some(square_sums) = square_sums.as_mut() {
+                    square_sums[index] = square_sums[index - stride] + row_square_sum;
+                }
+            }
+        }
+        IntegralImage { width, height, padding, stride, sums, square_sums }
+    }
+
+    /// Statistics of the inclusive rectangle (x0, y0)-(x1, y1) in image
+    /// coordinates, clipped to the area the table covers.
+    pub fn rectangle(&self, x0: i64, y0: i64, x1: i64, y1: i64) -> WindowStatistics {
+        let x0 = x0.max(-self.padding) + self.padding;
+        let y0 = y0.max(-self.padding) + self.padding;
+        let x1 = x1.min(self.width - 1 + self.padding) + self.padding;
+        let y1 = y1.min(self.height - 1 + self.padding) + self.padding;
+        if x0 > x1 || y0 > y1 {
+            return WindowStatistics { count: 0, sum: 0, square_sum: 0 };
+        }
+        let (x0, y0, x1, y1) = (x0 as usize, y0 as usize, x1 as usize + 1, y1 as usize + 1);
+        let area = |table: &Vec<u64>| {
+            table[y1 * self.stride + x1] + table[y0 * self.stride + x0]
+                - table[y0 * self.stride + x1] - table[y1 * self.stride + x0]
+        };
+        WindowStatistics {
+            count: ((x1 - x0) * (y1 - y0)) as u64,
+            sum: area(&self.sums),
+            square_sum: self.square_sums.as_ref().map_or(0, area),
+        }
+    }
+
+    /// Statistics of the square window of the given radius centered on (x, y).
+    pub fn window(&self, x: u32, y: u32, radius: u32) -> WindowStatistics {
+        let (x, y, radius) = (x as i64, y as i64, radius as i64);
+        self.rectangle(x - radius, y - radius, x + radius, y + radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{naive_sample, test_image, BORDERS};
+
+    fn naive_window(luma: &LumaBuffer, border: BorderMode, x: u32, y: u32, radius: u32) -> WindowStatistics {
+        let (x, y, radius) = (x as i64, y as i64, radius as i64);
+        let mut statistics = WindowStatistics { count: 0, sum: 0, square_sum: 0 };
+        for window_y in y - radius..=y + radius {
+            for window_x in x - radius..=x + radius {
+                if let Some(value) = naive_sample(luma, border, window_x, window_y) {
+                    statistics.count += 1;
+                    statistics.sum += value as u64;
+                    statistics.square_sum += value as u64 * value as u64;
+                }
+            }
+        }
+        statistics
+    }
+
+    #[test]
+    fn window_matches_naive_sum_in_every_border_mode() {
+        let luma = test_image(7, 5);
+        for border in BORDERS {
+            // A radius of 6 reaches more than one image size past the edge.
+            for radius in [0, 1, 3, 6] {
+                let integral = IntegralImage::with_squares(&luma, radius, border);
+                for (x, y, _) in luma.enumerate_pixels() {
+                    assert_eq!(integral.window(x, y, radius), naive_window(&luma, border, x, y, radius),
+                        "{:?}, radius {} at ({}, {})", border, radius, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rectangle_is_clipped_to_the_padding() {
+        let luma = test_image(4, 3);
+        let integral = IntegralImage::new(&luma, 1, BorderMode::Replicate);
+        let clipped = integral.rectangle(-5, -5, 10, 10);
+        assert_eq!(clipped.count, 6 * 5);
+        assert_eq!(clipped, integral.rectangle(-1, -1, 4, 3));
+        assert_eq!(integral.rectangle(3, 0, 2, 2).count, 0);
+    }
+
+    #[test]
+    fn new_leaves_out_the_squares() {
+        let luma = test_image(4, 3);
+        let statistics = IntegralImage::new(&luma, 0, BorderMode::NormalizeValid).window(1, 1, 1);
+        assert_eq!(statistics.square_sum, 0);
+        assert_eq!(statistics.sum, naive_window(&luma, BorderMode::NormalizeValid, 1, 1, 1).sum);
+    }
+
+    #[test]
+    fn mean_and_variance() {
+        let statistics = WindowStatistics { count: 4, sum: 10, square_sum: 30 };
+        assert_eq!(statistics.mean(), 2.5);
+        assert_eq!(statistics.variance(), 1.25);
+    }
+}
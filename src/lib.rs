@@ -0,0 +1,23 @@
+//! Image processing core of the simple image app.
+//!
+//! The algorithms, recipes and statistics work without a GUI. Enable the `iced`
+//! feature (on by default) to turn results into iced image handles.
+
+extern crate image as image_crate;
+
+pub use ::image;
+
+pub mod color;
+#[cfg(feature = "iced")]
+pub mod display;
+pub mod edge;
+pub mod filter;
+pub mod integral;
+pub mod parallel;
+pub mod process;
+pub mod progress;
+pub mod recipe;
+pub mod roi;
+pub mod statistics;
+#[cfg(test)]
+mod test_util;
@@ -2,16 +2,36 @@
 use std::fmt;
 use std::collections::HashMap;
 use std::error::Error;
-use iced::widget::{column, container, pick_list, text_input, Button, Column, Container, Image, Row, Text};
-use iced::{alignment, executor, Alignment, Application, Command, Element, Length, Renderer, Settings, Theme};
-use process::{image_load, process_image, ImagePanelData, ImageProcessError, ImageType, ProcessType};
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use iced::futures::{channel::mpsc, StreamExt};
+use iced::widget::{checkbox, column, container, pick_list, progress_bar, slider, text_input, Button, Column, Container, Row, Text};
+use iced::{alignment, executor, subscription, theme, Alignment, Application, Command, Element, Length, Renderer, Settings, Subscription, Theme, Vector};
+use simple_image_app::process::{
+    image_load, image_save, AdaptiveMethod, GrayscaleMethod, BorderMode, AdaptiveParameters, BinarizationParameters, CannyParameters, CropParameters, EdgeOutput, EdgeParameters, FilterParameters, GaussianParameters,
+    ImagePanelData, ImageProcessError, ImageType, OtsuParameters, ProcessParameters, ProcessType, RankKind,
+    RankParameters, WindowShape
+};
+use simple_image_app::color::{process_color_pipeline, ColorMode};
+use simple_image_app::parallel::set_thread_count;
+use simple_image_app::progress::ProcessProgress;
+use simple_image_app::recipe::{recipe_load, recipe_save, RECIPE_FILE_EXTENTIONS};
+use simple_image_app::roi::{restrict_to_roi, Roi};
+use simple_image_app::statistics::{ChannelStatistics, ImageStatistics, DEFAULT_PERCENTILES};
 use rfd::FileDialog;
+use histogram_chart::{HistogramChart, PanelHistogram};
+use viewer::{PanelDisplay, PanelView, RoiTool, ZoomImage};
 
 const LOAD_FILE_EXTENTIONS: &[&str; 7]  = &["jpg", "jpeg", "png", "bmp", "gif", "tiff", "tif"];
+const SAVE_FILE_EXTENTIONS: &[&str; 6]  = &["png", "tiff", "tif", "bmp", "jpg", "jpeg"];
+const DEFAULT_JPEG_QUALITY: u8 = 90;
+const PROGRESS_REFRESH_INTERVAL: Duration = Duration::from_millis(100);
 
-mod process;
+mod histogram_chart;
+mod viewer;
 fn main() {
-
     let mut setting: Settings<()> = Settings::default();
     setting.window.size = (1200, 900);
     let _ = ImageProcessSample::run(setting);
@@ -20,17 +40,55 @@ fn main() {
 #[derive(Debug, Clone)]
 enum Message {
     ProcessTypeSelected(ProcessType),
+    GrayscaleMethodSelected(GrayscaleMethod),
+    ColorModeSelected(ColorMode),
+    ProcessParametersChanged(ProcessParameters),
+    /// Text typed into a parameter input, with the parameters it sets if it is a number.
+    ParameterTextChanged(&'static str, String, Option<ProcessParameters>),
+    AddStep,
+    RemoveStep(usize),
+    MoveStepUp(usize),
+    MoveStepDown(usize),
+    SelectStep(usize),
+    InspectStep(usize),
+    PanelViewChanged(ImageType, PanelView),
+    PixelHovered(ImageType, Option<(u32, u32)>),
+    LockViewsToggled(bool),
+    HistogramLogToggled(bool),
+    PercentilesChanged(String),
+    RoiToolSelected(RoiTool),
+    RoiDrawn(ImageType, Roi),
+    CropToRoi,
+    ClearRoi,
+    ProcessInRoiToggled(bool),
     PathChanged(String),
     ImageLoad,
-    Process,
+    Process(Vec<ProcessParameters>),
     ShowFileDialog,
-    ProcessEnd(Result<ImagePanelData, ImageProcessError>),
+    SaveRecipe,
+    LoadRecipe,
+    SaveImage,
+    JpegQualityChanged(u8),
+    ThreadCountChanged(u32),
+    CancelProcess,
+    ProgressTick,
+    ProcessEnd(Result<Vec<ImagePanelData>, ImageProcessError>),
 }
 
 struct  UserInteractItems {
     process_type: ProcessType,
+    grayscale_method: GrayscaleMethod,
+    color_mode: ColorMode,
+    pipeline: Vec<ProcessParameters>,
+    selected_step: Option<usize>,
+    inspectable_steps: usize,
+    jpeg_quality: u8,
+    thread_count: u32,
     path: String,
     is_image_loaded: bool,
+    image_size: (u32, u32),
+    /// Text of the parameter input being typed in: step index, caption and text.
+    parameter_text: Option<(usize, &'static str, String)>,
 }
 
 enum PanelInfoImageValueState {
@@ -49,15 +107,33 @@ impl fmt::Display for PanelInfoImageValueState {
 struct PanelInformation {
     image_load_result: String,
     image_process_result: String,
+    recipe_result: String,
+    image_save_result: String,
     processed_type: ProcessType,
-    image_width: PanelInfoImageValueState,
-    image_height: PanelInfoImageValueState,
-    max_image_value: PanelInfoImageValueState,
-    min_image_value: PanelInfoImageValueState,
+    displayed_step: String,
+    threshold_value: PanelInfoImageValueState,
+    cursor_position: String,
+    original_value: String,
+    gray_value: String,
+    processed_value: String,
 }   
 #[derive(Debug, Clone)]
 struct ImagePanel {
     images: HashMap<ImageType, ImagePanelData>,
+    displays: HashMap<ImageType, PanelDisplay>,
+    histograms: HashMap<ImageType, PanelHistogram>,
+    histogram_log: bool,
+    statistics: HashMap<ImageType, ImageStatistics>,
+    percentiles: Vec<f64>,
+    percentiles_text: String,
+    roi: Option<Roi>,
+    roi_tool: RoiTool,
+    process_in_roi: bool,
+    views: HashMap<ImageType, PanelView>,
+    lock_views: bool,
+    hovered_pixel: Option<(ImageType, (u32, u32))>,
+    step_results: Vec<ImagePanelData>,
+    processed_steps: Vec<ProcessParameters>,
 }
 
 struct ImageProcessSample {
@@ -65,6 +141,8 @@ struct ImageProcessSample {
     panel_information: PanelInformation,
     image_panel: ImagePanel,
     is_processing: bool,
+    progress: Arc<ProcessProgress>,
+    running_steps: Vec<ProcessParameters>,
 }
 
 trait RowContent {
@@ -76,17 +154,43 @@ impl RowContent for UserInteractItems {
     fn new() -> Self {
         UserInteractItems {
             process_type: ProcessType::None,
+            grayscale_method: GrayscaleMethod::default(),
+            color_mode: ColorMode::default(),
+            pipeline: Vec::new(),
+            selected_step: None,
+            inspectable_steps: 0,
+            jpeg_quality: DEFAULT_JPEG_QUALITY,
+            thread_count: 0,
             path: String::from(""),
             is_image_loaded: false,
+            image_size: (0, 0),
+            parameter_text: None,
         }
     }
     fn to_row(&self) -> Row<'static, Message> {
+        let grayscale_pick_list = pick_list(
+            GrayscaleMethod::ALL,
+            Some(self.grayscale_method),
+            Message::GrayscaleMethodSelected,
+        );
+
+        let color_mode_pick_list = pick_list(
+            ColorMode::ALL,
+            Some(self.color_mode),
+            Message::ColorModeSelected,
+        );
+
         let pick_list = pick_list(
             ProcessType::ALL,
             Some(self.process_type),
             Message::ProcessTypeSelected,
         );
 
+        let add_step_button = Button::new(
+            Text::new("Add step"),
+        )
+        .on_press(Message::AddStep);
+
         let load_button = 
         Button::new(Text::new("Load"),
         )
@@ -97,7 +201,7 @@ impl RowContent for UserInteractItems {
         let process_button = Button::new(
             Text::new("Process"),
         )
-        .on_press(Message::Process);
+        .on_press(Message::Process(self.pipeline.clone()));
 
         let file_dialog  = Button::new(
             Text::new("Select"),
@@ -105,6 +209,32 @@ impl RowContent for UserInteractItems {
         .on_press(Message::ShowFileDialog)
         ;
 
+        let save_image_button = Button::new(
+            Text::new("Save"),
+        )
+        .on_press(Message::SaveImage);
+
+        let jpeg_quality_slider = slider(1..=100, self.jpeg_quality, Message::JpegQualityChanged)
+            .width(Length::Fixed(150.0));
+
+        let max_threads = std::thread::available_parallelism().map_or(1, |threads| threads.get()) as u32;
+        let thread_count_slider = slider(0..=max_threads, self.thread_count, Message::ThreadCountChanged)
+            .width(Length::Fixed(100.0));
+        let thread_count_text = match self.thread_count {
+            0 => String::from("Threads: all"),
+            threads => format!("Threads: {}", threads),
+        };
+
+        let save_recipe_button = Button::new(
+            Text::new("Save Recipe"),
+        )
+        .on_press(Message::SaveRecipe);
+
+        let load_recipe_button = Button::new(
+            Text::new("Load Recipe"),
+        )
+        .on_press(Message::LoadRecipe);
+
         let text_input = 
         text_input("image file path", &self.path,)
         .width(Length::Fill)
@@ -114,13 +244,25 @@ impl RowContent for UserInteractItems {
             .spacing(10)
             .align_items(Alignment::Start)
             .push(pick_list)
-            .push(process_button);
+            .push(add_step_button)
+            .push(process_button)
+            .push(save_image_button)
+            .push(Text::new(format!("JPEG quality: {}", self.jpeg_quality)))
+            .push(jpeg_quality_slider)
+            .push(Text::new(thread_count_text))
+            .push(thread_count_slider);
 
         let file_path_row = Row::new()
             .spacing(10)
             .align_items(Alignment::Start)
             .push(file_dialog)
             .push(load_button)
+            .push(Text::new("Grayscale:"))
+            .push(grayscale_pick_list)
+            .push(Text::new("Color:"))
+            .push(color_mode_pick_list)
+            .push(save_recipe_button)
+            .push(load_recipe_button)
             .push(text_input);
 
         let user_interact_items_row = Row::new()
@@ -128,6 +270,8 @@ impl RowContent for UserInteractItems {
             .push(        column![
                 column![file_path_row].padding(10),
                 column![process_row].padding(10),
+                self.pipeline_rows().padding(10),
+                self.parameter_rows().padding(10),
             ])
             .width(Length::Fill)
             .height(Length::Fill)
@@ -137,17 +281,275 @@ impl RowContent for UserInteractItems {
     }
 }
 
+impl UserInteractItems {
+    fn pipeline_rows(&self) -> Column<'static, Message> {
+        let mut rows = Column::new().spacing(5);
+        for (index, step) in self.pipeline.iter().enumerate() {
+            let style = if self.selected_step == Some(index) {
+                theme::Button::Primary
+            } else {
+                theme::Button::Secondary
+            };
+            let select_button = Button::new(Text::new(format!("{}. {}", index + 1, step.process_type())))
+                .width(Length::Fixed(300.0))
+                .style(style)
+                .on_press(Message::SelectStep(index));
+            let mut up_button = Button::new(Text::new("Up"));
+            if index > 0 {
+                up_button = up_button.on_press(Message::MoveStepUp(index));
+            }
+            let mut down_button = Button::new(Text::new("Down"));
+            if index + 1 < self.pipeline.len() {
+                down_button = down_button.on_press(Message::MoveStepDown(index));
+            }
+            let remove_button = Button::new(Text::new("Remove"))
+                .on_press(Message::RemoveStep(index));
+            let mut show_button = Button::new(Text::new("Show"));
+            if index < self.inspectable_steps {
+                show_button = show_button.on_press(Message::InspectStep(index));
+            }
+            rows = rows.push(
+                Row::new()
+                    .spacing(10)
+                    .align_items(Alignment::Center)
+                    .push(select_button)
+                    .push(up_button)
+                    .push(down_button)
+                    .push(remove_button)
+                    .push(show_button)
+            );
+        }
+        rows
+    }
+
+    /// Origin and size of the image the step at `index` runs on, following the
+    /// crops before it.
+    fn step_input_area(&self, index: usize) -> ((u32, u32), (u32, u32)) {
+        self.pipeline[..index].iter().fold(((0, 0), self.image_size), |(origin, size), step| match step {
+            ProcessParameters::Crop(parameters) => match parameters.rectangle(size.0, size.1) {
+                Some((x, y, width, height)) => ((origin.0 + x, origin.1 + y), (width, height)),
+                None => (origin, size),
+            },
+            _ => (origin, size),
+        })
+    }
+
+    fn parameter_rows(&self) -> Column<'static, Message> {
+        let rows = Column::new().spacing(5);
+        let Some(index) = self.selected_step else {
+            return rows;
+        };
+        match self.pipeline[index] {
+            ProcessParameters::None => rows,
+            ProcessParameters::Crop(parameters) => {
+                let (_, (width, height)) = self.step_input_area(index);
+                let (width, height) = (width as f64, height as f64);
+                rows
+                    .push(self.parameter_row("X", parameters.x as f64, 0.0..=(width - 1.0).max(0.0), 1.0,
+                        move |value| ProcessParameters::Crop(CropParameters { x: value as u32, ..parameters })))
+                    .push(self.parameter_row("Y", parameters.y as f64, 0.0..=(height - 1.0).max(0.0), 1.0,
+                        move |value| ProcessParameters::Crop(CropParameters { y: value as u32, ..parameters })))
+                    .push(self.parameter_row("Width (0 = to edge)", parameters.width as f64, 0.0..=width, 1.0,
+                        move |value| ProcessParameters::Crop(CropParameters { width: value as u32, ..parameters })))
+                    .push(self.parameter_row("Height (0 = to edge)", parameters.height as f64, 0.0..=height, 1.0,
+                        move |value| ProcessParameters::Crop(CropParameters { height: value as u32, ..parameters })))
+                    .push(Button::new(Text::new("Use drawn ROI")).on_press(Message::CropToRoi))
+            },
+            ProcessParameters::Binarization(parameters) => rows
+                .push(self.parameter_row("Threshold", parameters.threshold as f64, 0.0..=u16::MAX as f64, 1.0,
+                    move |value| ProcessParameters::Binarization(BinarizationParameters { threshold: value as u16, ..parameters })))
+                .push(self.parameter_row("Max color", parameters.max_color as f64, 0.0..=u8::MAX as f64, 1.0,
+                    move |value| ProcessParameters::Binarization(BinarizationParameters { max_color: value as u8, ..parameters }))),
+            ProcessParameters::OtsuBinarization(parameters) => rows
+                .push(self.parameter_row("Max color", parameters.max_color as f64, 0.0..=u8::MAX as f64, 1.0,
+                    move |value| ProcessParameters::OtsuBinarization(OtsuParameters { max_color: value as u8 }))),
+            ProcessParameters::AdaptiveBinarization { method, parameters } => {
+                let rows = rows
+                    .push(self.parameter_row("Window size", parameters.window_size as f64, 1.0..=101.0, 2.0,
+                        move |value| ProcessParameters::AdaptiveBinarization { method, parameters: AdaptiveParameters { window_size: value as u32, ..parameters } }))
+                    .push(self.parameter_row("Offset", parameters.offset, -8192.0..=8192.0, 1.0,
+                        move |value| ProcessParameters::AdaptiveBinarization { method, parameters: AdaptiveParameters { offset: value, ..parameters } }));
+                let rows = match method {
+                    AdaptiveMethod::Sauvola | AdaptiveMethod::Niblack => rows
+                        .push(self.parameter_row("k", parameters.k, -1.0..=1.0, 0.01,
+                            move |value| ProcessParameters::AdaptiveBinarization { method, parameters: AdaptiveParameters { k: value, ..parameters } })),
+                    _ => rows,
+                };
+                rows.push(self.parameter_row("Max color", parameters.max_color as f64, 0.0..=u8::MAX as f64, 1.0,
+                    move |value| ProcessParameters::AdaptiveBinarization { method, parameters: AdaptiveParameters { max_color: value as u8, ..parameters } }))
+                    .push(self.border_rows(parameters.border,
+                        move |border| ProcessParameters::AdaptiveBinarization { method, parameters: AdaptiveParameters { border, ..parameters } }))
+            },
+            ProcessParameters::ConvolveFilterAVG(parameters) => rows
+                .push(self.parameter_row("Filter size", parameters.filter_size as f64, 1.0..=101.0, 2.0,
+                    move |value| ProcessParameters::ConvolveFilterAVG(FilterParameters { filter_size: value as u32, ..parameters })))
+                .push(self.border_rows(parameters.border,
+                    move |border| ProcessParameters::ConvolveFilterAVG(FilterParameters { border, ..parameters }))),
+            ProcessParameters::GaussianBlur(parameters) => rows
+                .push(self.parameter_row("Sigma", parameters.sigma, 0.1..=50.0, 0.1,
+                    move |value| ProcessParameters::GaussianBlur(GaussianParameters { sigma: value, ..parameters })))
+                .push(self.parameter_row("Kernel size (0 = auto)", parameters.kernel_size as f64, 0.0..=301.0, 1.0,
+                    move |value| ProcessParameters::GaussianBlur(GaussianParameters { kernel_size: value as u32, ..parameters })))
+                .push(self.border_rows(parameters.border,
+                    move |border| ProcessParameters::GaussianBlur(GaussianParameters { border, ..parameters }))),
+            ProcessParameters::RankFilter { rank, parameters } => {
+                let shape_row = Row::new()
+                    .spacing(10)
+                    .align_items(Alignment::Center)
+                    .push(Text::new("Window shape").width(Length::Fixed(100.0)))
+                    .push(pick_list(&WindowShape::ALL[..], Some(parameters.shape), move |shape| {
+                        Message::ProcessParametersChanged(ProcessParameters::RankFilter { rank, parameters: RankParameters { shape, ..parameters } })
+                    }));
+                let rows = rows
+                    .push(self.parameter_row("Window size", parameters.window_size as f64, 1.0..=101.0, 2.0,
+                        move |value| ProcessParameters::RankFilter { rank, parameters: RankParameters { window_size: value as u32, ..parameters } }))
+                    .push(shape_row)
+                    .push(self.border_rows(parameters.border,
+                        move |border| ProcessParameters::RankFilter { rank, parameters: RankParameters { border, ..parameters } }));
+                match rank {
+                    RankKind::Percentile => rows
+                        .push(self.parameter_row("Percentile", parameters.percentile, 0.0..=100.0, 0.5,
+                            move |value| ProcessParameters::RankFilter { rank, parameters: RankParameters { percentile: value, ..parameters } })),
+                    _ => rows,
+                }
+            },
+            ProcessParameters::EdgeDetection { operator, parameters } => {
+                let output_row = Row::new()
+                    .spacing(10)
+                    .align_items(Alignment::Center)
+                    .push(Text::new("Output").width(Length::Fixed(100.0)))
+                    .push(pick_list(&EdgeOutput::ALL[..], Some(parameters.output), move |output| {
+                        Message::ProcessParametersChanged(ProcessParameters::EdgeDetection { operator, parameters: EdgeParameters { output, ..parameters } })
+                    }));
+                let normalize_checkbox = checkbox("Normalize", parameters.normalize, move |normalize| {
+                    Message::ProcessParametersChanged(ProcessParameters::EdgeDetection { operator, parameters: EdgeParameters { normalize, ..parameters } })
+                });
+                let rows = rows
+                    .push(output_row)
+                    .push(normalize_checkbox);
+                let rows = match parameters.normalize {
+                    true => rows
+                        .push(self.parameter_row("Clip percentile", parameters.clip_percentile, 0.0..=100.0, 0.1,
+                            move |value| ProcessParameters::EdgeDetection { operator, parameters: EdgeParameters { clip_percentile: value, ..parameters } })),
+                    false => rows,
+                };
+                rows.push(self.border_rows(parameters.border,
+                    move |border| ProcessParameters::EdgeDetection { operator, parameters: EdgeParameters { border, ..parameters } }))
+            },
+            ProcessParameters::Canny(parameters) => {
+                let automatic_checkbox = checkbox("Automatic thresholds", parameters.automatic, move |automatic| {
+                    Message::ProcessParametersChanged(ProcessParameters::Canny(CannyParameters { automatic, ..parameters }))
+                });
+                let rows = rows
+                    .push(self.parameter_row("Sigma (0 = none)", parameters.sigma, 0.0..=10.0, 0.1,
+                        move |value| ProcessParameters::Canny(CannyParameters { sigma: value, ..parameters })))
+                    .push(automatic_checkbox);
+                let rows = match parameters.automatic {
+                    true => rows,
+                    false => rows
+                        .push(self.parameter_row("Low threshold", parameters.low_threshold as f64, 0.0..=u16::MAX as f64, 1.0,
+                            move |value| ProcessParameters::Canny(CannyParameters { low_threshold: value as u16, ..parameters })))
+                        .push(self.parameter_row("High threshold", parameters.high_threshold as f64, 0.0..=u16::MAX as f64, 1.0,
+                            move |value| ProcessParameters::Canny(CannyParameters { high_threshold: value as u16, ..parameters }))),
+                };
+                rows.push(self.border_rows(parameters.border,
+                    move |border| ProcessParameters::Canny(CannyParameters { border, ..parameters })))
+            },
+        }
+    }
+
+    /// Border mode picker, plus the fill value while the mode is `Constant`.
+    fn border_rows<F>(&self, border: BorderMode, to_parameters: F) -> Column<'static, Message>
+    where
+    F: Fn(BorderMode) -> ProcessParameters + Copy + 'static,
+    {
+        let constant = match border {
+            BorderMode::Constant(value) => value,
+            _ => 0,
+        };
+        let mode_row = Row::new()
+            .spacing(10)
+            .align_items(Alignment::Center)
+            .push(Text::new("Border").width(Length::Fixed(100.0)))
+            .push(pick_list(BorderMode::options(constant).to_vec(), Some(border), move |border| {
+                Message::ProcessParametersChanged(to_parameters(border))
+            }));
+        let rows = Column::new().spacing(5).push(mode_row);
+        match border {
+            BorderMode::Constant(value) => rows
+                .push(self.parameter_row("Border value", value as f64, 0.0..=u16::MAX as f64, 1.0,
+                    move |value| to_parameters(BorderMode::Constant(value as u16)))),
+            _ => rows,
+        }
+    }
+
+    /// A labelled slider with a numeric input next to it; both report the new value
+    /// through `to_parameters`. The input keeps text that is not a number yet,
+    /// such as "-", without changing the value.
+    fn parameter_row<F>(&self, caption: &'static str, value: f64, range: RangeInclusive<f64>, step: f64, to_parameters: F) -> Row<'static, Message>
+    where
+    F: Fn(f64) -> ProcessParameters + Copy + 'static,
+    {
+        let (min, max) = (*range.start(), *range.end());
+        let slider = slider(range, value, move |value| {
+            Message::ProcessParametersChanged(to_parameters(snap_to_step(value, min, step)))
+        })
+        .step(step)
+        .width(Length::Fixed(300.0));
+        let text = match &self.parameter_text {
+            Some((index, edited, text)) if self.selected_step == Some(*index) && *edited == caption => text.clone(),
+            _ => value.to_string(),
+        };
+        let input = text_input(caption, &text)
+            .width(Length::Fixed(100.0))
+            .on_input(move |text| {
+                let process_parameters = text.trim().parse::<f64>().ok().map(|value| to_parameters(snap_to_step(value.clamp(min, max), min, step)));
+                Message::ParameterTextChanged(caption, text, process_parameters)
+            });
+        Row::new()
+            .spacing(10)
+            .align_items(Alignment::Center)
+            .push(Text::new(String::from(caption)).width(Length::Fixed(100.0)))
+            .push(slider)
+            .push(input)
+    }
+}
+
+/// `value` rounded to a multiple of `step` counted from `min`, without the
+/// binary noise of decimal steps (0.3 rather than 0.30000000000000004).
+fn snap_to_step(value: f64, min: f64, step: f64) -> f64 {
+    let snapped = min + ((value - min) / step).round() * step;
+    (snapped * 1e6).round() / 1e6
+}
+
+/// Reads a comma separated list of percentiles from 0 to 100; `None` until
+/// every entry is valid.
+fn parse_percentiles(text: &str) -> Option<Vec<f64>> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| entry.parse::<f64>().ok().filter(|percent| (0.0..=100.0).contains(percent)))
+        .collect()
+}
+
+fn percentiles_text(percentiles: &[f64]) -> String {
+    percentiles.iter().map(f64::to_string).collect::<Vec<_>>().join(", ")
+}
 
 impl RowContent for PanelInformation {
     fn new() -> Self {
         PanelInformation {
             image_load_result: String::from(""),
             image_process_result: String::from(""),
+            recipe_result: String::from(""),
+            image_save_result: String::from(""),
             processed_type: ProcessType::None,
-            image_width: PanelInfoImageValueState::Unset,
-            image_height: PanelInfoImageValueState::Unset,
-            max_image_value: PanelInfoImageValueState::Unset,
-            min_image_value: PanelInfoImageValueState::Unset,
+            displayed_step: String::from(""),
+            threshold_value: PanelInfoImageValueState::Unset,
+            cursor_position: String::from(""),
+            original_value: String::from(""),
+            gray_value: String::from(""),
+            processed_value: String::from(""),
         }
     }
     fn to_row(&self) -> Row<'static, Message> {
@@ -162,11 +564,15 @@ impl RowContent for PanelInformation {
         }
         let iamge_load_result_row = create_row("Image load result: ", &self.image_load_result);
         let image_process_result_row = create_row("Image process result: ", &self.image_process_result);
+        let recipe_result_row = create_row("Recipe result: ", &self.recipe_result);
+        let image_save_result_row = create_row("Image save result: ", &self.image_save_result);
         let processed_type_row = create_row("Processed type: ", &self.processed_type.to_string());
-        let image_width_row = create_row("Image width: ", &self.image_width.to_string());
-        let image_height_row = create_row("Image height: ", &self.image_height.to_string());
-        let max_image_value_row = create_row("Maximum pixel value (16-bit): ", &self.max_image_value.to_string());
-        let min_image_value_row = create_row("Minimum pixel value (16-bit): ", &self.min_image_value.to_string());
+        let displayed_step_row = create_row("Displayed step: ", &self.displayed_step);
+        let threshold_value_row = create_row("Threshold value (16-bit): ", &self.threshold_value.to_string());
+        let cursor_position_row = create_row("Cursor (x, y): ", &self.cursor_position);
+        let original_value_row = create_row("Original RGBA: ", &self.original_value);
+        let gray_value_row = create_row("Gray value (16-bit): ", &self.gray_value);
+        let processed_value_row = create_row("Processed value: ", &self.processed_value);
     
         let panel_information_row = Row::new()
             .align_items(alignment::Alignment::Start)
@@ -174,11 +580,15 @@ impl RowContent for PanelInformation {
                 column![
                     column![iamge_load_result_row].padding(3),
                     column![image_process_result_row].padding(3),
+                    column![recipe_result_row].padding(3),
+                    column![image_save_result_row].padding(3),
                     column![processed_type_row].padding(3),
-                    column![image_width_row].padding(3),
-                    column![image_height_row].padding(3),
-                    column![max_image_value_row].padding(3),
-                    column![min_image_value_row].padding(3),
+                    column![displayed_step_row].padding(3),
+                    column![threshold_value_row].padding(3),
+                    column![cursor_position_row].padding(3),
+                    column![original_value_row].padding(3),
+                    column![gray_value_row].padding(3),
+                    column![processed_value_row].padding(3),
                 ]
             )
             .width(Length::Fill)
@@ -194,28 +604,70 @@ impl RowContent for ImagePanel {
         let images = HashMap::new();
         ImagePanel {
             images,
+            displays: HashMap::new(),
+            histograms: HashMap::new(),
+            histogram_log: false,
+            statistics: HashMap::new(),
+            percentiles: DEFAULT_PERCENTILES.to_vec(),
+            percentiles_text: percentiles_text(DEFAULT_PERCENTILES),
+            roi: None,
+            roi_tool: RoiTool::default(),
+            process_in_roi: false,
+            views: HashMap::new(),
+            lock_views: false,
+            hovered_pixel: None,
+            step_results: Vec::new(),
+            processed_steps: Vec::new(),
         }
     }
     fn to_row(&self) -> Row<'static, Message> {
-        let row = Row::new()
+        let panels = Row::new()
             .spacing(0)
             .align_items(Alignment::Center)
             .push(self.create_image_panel(ImageType::Original))
             .push(self.create_image_panel(ImageType::Grayscale))
             .push(self.create_image_panel(ImageType::Processed));
-        row.padding(50)
+        let lock_views_checkbox = checkbox("Lock zoom and pan", self.lock_views, Message::LockViewsToggled);
+        let histogram_log_checkbox = checkbox("Log histogram", self.histogram_log, Message::HistogramLogToggled);
+        let percentiles_input = text_input("1, 5, 95, 99", &self.percentiles_text)
+            .width(Length::Fixed(200.0))
+            .on_input(Message::PercentilesChanged);
+        let options = Row::new()
+            .spacing(20)
+            .align_items(Alignment::Center)
+            .push(lock_views_checkbox)
+            .push(histogram_log_checkbox)
+            .push(Text::new("Percentiles:"))
+            .push(percentiles_input);
+        let clear_roi_button = Button::new(Text::new("Clear ROI"));
+        let roi_options = Row::new()
+            .spacing(20)
+            .align_items(Alignment::Center)
+            .push(Text::new("Draw:"))
+            .push(pick_list(RoiTool::ALL, Some(self.roi_tool), Message::RoiToolSelected))
+            .push(match self.roi {
+                Some(_) => clear_roi_button.on_press(Message::ClearRoi),
+                None => clear_roi_button,
+            })
+            .push(checkbox("Process inside ROI only", self.process_in_roi, Message::ProcessInRoiToggled));
+        Row::new()
+            .push(column![panels, options, roi_options].spacing(5))
+            .padding(50)
     }}
 
 impl ImagePanel {
 
-    fn to_content(&self, image_type: ImageType) -> Element<'static, Message, Renderer> {
-        if !self.images.contains_key(&image_type) {
-            return Container::new(Text::new("No Image")).into();
-        }
-        match image_type {
-            ImageType::Original => Image::new(self.images[&image_type].to_rgba8_image_handle().clone()).into(),
-            _=> Image::new(self.images[&image_type].to_handle().clone()).into(),
-        }
+    fn to_content(&self, image_type: ImageType, height: f32) -> Element<'static, Message, Renderer> {
+        let Some(display) = self.displays.get(&image_type) else {
+            return Container::new(Text::new("No Image")).height(Length::Fixed(height)).center_y().into();
+        };
+        let view = self.views.get(&image_type).copied().unwrap_or_default();
+        ZoomImage::new(display, view, move |view| Message::PanelViewChanged(image_type, view))
+            .on_hover(move |position| Message::PixelHovered(image_type, position))
+            .roi(self.panel_roi(image_type))
+            .on_roi(self.roi_tool, move |roi| Message::RoiDrawn(image_type, roi))
+            .height(Length::Fixed(height))
+            .into()
     }
     fn create_image_panel(&self, image_type: ImageType) -> Container<'static, Message> {
         let grid_cell_style = |_: &iced::Theme| container::Appearance {
@@ -226,16 +678,189 @@ impl ImagePanel {
             border_radius: 0.0.into(),
         };
         let row_height = 300.0; 
-        container(self.to_content(image_type))
+        let view_buttons = Row::new()
+            .spacing(5)
+            .push(Button::new(Text::new("Fit")).on_press(Message::PanelViewChanged(image_type, PanelView::FIT)))
+            .push(Button::new(Text::new("100%")).on_press(Message::PanelViewChanged(image_type, PanelView::ACTUAL_SIZE)));
+        let mut content = column![self.to_content(image_type, row_height)].spacing(5).align_items(Alignment::Center);
+        if let Some(histogram) = self.histograms.get(&image_type) {
+            content = content.push(HistogramChart::new(histogram, self.histogram_log));
+        }
+        container(content.push(view_buttons).push(self.statistics_table(image_type)))
                     .width(Length::Fill)
-                    .height(Length::Fixed(row_height))
                     .center_x()
                     .center_y()
                     .style(grid_cell_style)
     }
 
-    fn image_load(&mut self, path: &str) ->  Result<String, Box<dyn Error>>{
-         image_load(& mut self.images, path) 
+    /// Luminance and, for color images, per-channel statistics of a panel.
+    fn statistics_table(&self, image_type: ImageType) -> Element<'static, Message, Renderer> {
+        let Some(statistics) = self.statistics.get(&image_type) else {
+            return Column::new().into();
+        };
+        let mut channels = vec![("Luma", &statistics.luma)];
+        channels.extend(["R", "G", "B", "A"].into_iter().zip(&statistics.channels));
+        let row = |caption: String, value: &dyn Fn(&ChannelStatistics) -> String| {
+            (caption, channels.iter().map(|(_, channel)| value(channel)).collect::<Vec<_>>())
+        };
+        let mut rows = vec![
+            row(String::from("Min"), &|channel| channel.min().to_string()),
+            row(String::from("Max"), &|channel| channel.max().to_string()),
+            row(String::from("Mean"), &|channel| format!("{:.1}", channel.mean())),
+            row(String::from("Std dev"), &|channel| format!("{:.1}", channel.std_dev())),
+            row(String::from("Median"), &|channel| channel.median().to_string()),
+        ];
+        for &percent in &self.percentiles {
+            rows.push(row(format!("P{}", percent), &|channel| channel.percentile(percent).to_string()));
+        }
+        rows.push(row(String::from("Entropy (bits)"), &|channel| format!("{:.3}", channel.entropy())));
+        rows.push(row(String::from("Zero"), &|channel| channel.zero().to_string()));
+        rows.push(row(String::from("Saturated"), &|channel| channel.saturated().to_string()));
+
+        let cell = |value: String| Text::new(value).size(14);
+        let mut captions = Column::new().push(cell(String::new()));
+        for (caption, _) in &rows {
+            captions = captions.push(cell(caption.clone()));
+        }
+        let mut table = Row::new().spacing(15).push(captions);
+        for (index, (name, _)) in channels.iter().enumerate() {
+            let mut values = Column::new().align_items(Alignment::End).push(cell(String::from(*name)));
+            for (_, row_values) in &rows {
+                values = values.push(cell(row_values[index].clone()));
+            }
+            table = table.push(values);
+        }
+        let size = match self.roi {
+            Some(_) => format!("{} x {}, {} pixels in ROI", statistics.width, statistics.height, statistics.luma.count()),
+            None => format!("{} x {}", statistics.width, statistics.height),
+        };
+        column![cell(size), table]
+            .spacing(3)
+            .padding(5)
+            .into()
+    }
+
+    /// Sets the view of `image_type`, or of every panel when views are locked.
+    fn set_view(&mut self, image_type: ImageType, view: PanelView) {
+        if self.lock_views {
+            // Centers are moved so that cropped panels show the same part of the image.
+            let (left, top) = self.origin(image_type);
+            for target in [ImageType::Original, ImageType::Grayscale, ImageType::Processed] {
+                let (target_left, target_top) = self.origin(target);
+                let shift = Vector::new(left as f32 - target_left as f32, top as f32 - target_top as f32);
+                let center = view.center.map(|center| center + shift);
+                self.views.insert(target, PanelView { center, ..view });
+            }
+        } else {
+            self.views.insert(image_type, view);
+        }
+    }
+
+    /// Rebuilds what the panel of `image_type` draws after its image changed.
+    fn refresh_display(&mut self, image_type: ImageType) {
+        match self.images.get(&image_type) {
+            Some(image_panel_data) => self.displays.insert(image_type, PanelDisplay::new(image_panel_data)),
+            None => self.displays.remove(&image_type),
+        };
+        self.refresh_statistics(image_type);
+        if image_type == ImageType::Processed {
+            // The Grayscale histogram marks the threshold of the Processed image.
+            self.refresh_statistics(ImageType::Grayscale);
+        }
+    }
+
+    /// Where the image of `image_type` starts in the loaded image.
+    fn origin(&self, image_type: ImageType) -> (u32, u32) {
+        self.images.get(&image_type).map_or((0, 0), ImagePanelData::get_origin)
+    }
+
+    /// The ROI in the coordinates of the panel of `image_type`.
+    fn panel_roi(&self, image_type: ImageType) -> Option<Roi> {
+        let (left, top) = self.origin(image_type);
+        self.roi.as_ref().map(|roi| roi.translated(-(left as f32), -(top as f32)))
+    }
+
+    /// Recomputes the statistics and histogram of `image_type`, inside the ROI when there is one.
+    fn refresh_statistics(&mut self, image_type: ImageType) {
+        let Some(image_panel_data) = self.images.get(&image_type) else {
+            self.histograms.remove(&image_type);
+            self.statistics.remove(&image_type);
+            return;
+        };
+        let per_channel = image_type == ImageType::Original;
+        let statistics = match self.panel_roi(image_type) {
+            Some(roi) => image_panel_data.roi_statistics(&roi),
+            None => image_panel_data.statistics(),
+        };
+        // A binarization threshold splits the histogram of its input, which is the Grayscale image.
+        let threshold = match image_type {
+            ImageType::Grayscale => self.images.get(&ImageType::Processed).and_then(ImagePanelData::get_threshold),
+            _ => image_panel_data.get_threshold(),
+        };
+        self.histograms.insert(image_type, PanelHistogram::new(&statistics, threshold, per_channel));
+        self.statistics.insert(image_type, statistics);
+    }
+
+    fn set_roi(&mut self, roi: Option<Roi>) {
+        self.roi = roi;
+        for image_type in [ImageType::Original, ImageType::Grayscale, ImageType::Processed] {
+            self.refresh_statistics(image_type);
+        }
+    }
+
+    fn image_load(&mut self, path: &str, method: GrayscaleMethod) ->  Result<String, Box<dyn Error>>{
+         self.step_results.clear();
+         self.processed_steps.clear();
+         self.views.clear();
+         self.roi = None;
+         let result = image_load(& mut self.images, path, method);
+         for image_type in [ImageType::Original, ImageType::Grayscale, ImageType::Processed] {
+             self.refresh_display(image_type);
+         }
+         result
+    }
+
+    /// Converts the Original to gray, cropped to the part of the image the
+    /// Processed panel shows so that the Grayscale panel follows a crop.
+    fn grayscale_update(&mut self, method: GrayscaleMethod) {
+        if let Some(original) = self.images.get(&ImageType::Original) {
+            let grayscale = match self.images.get(&ImageType::Processed) {
+                Some(processed) => {
+                    let (left, top) = processed.get_origin();
+                    original.crop(left, top, processed.get_image_width() as u32, processed.get_image_height() as u32).to_grayscale(method)
+                },
+                None => original.to_grayscale(method),
+            };
+            self.images.insert(ImageType::Grayscale, grayscale);
+            self.refresh_display(ImageType::Grayscale);
+        }
+    }
+
+    /// Crops the Grayscale panel again when the Processed panel now shows
+    /// another part of the image.
+    fn grayscale_follow(&mut self, method: GrayscaleMethod) {
+        let area = |image_type| {
+            self.images.get(&image_type).map(|image_panel_data: &ImagePanelData| {
+                (image_panel_data.get_origin(), image_panel_data.get_image_width(), image_panel_data.get_image_height())
+            })
+        };
+        if area(ImageType::Grayscale) != area(ImageType::Processed) {
+            self.grayscale_update(method);
+        }
+    }
+
+    /// The image a pipeline in `color_mode` starts from: the Original, or its
+    /// whole gray conversion even while the Grayscale panel is cropped.
+    fn pipeline_input(&self, color_mode: ColorMode, method: GrayscaleMethod) -> Option<ImagePanelData> {
+        let original = self.images.get(&ImageType::Original)?;
+        if color_mode != ColorMode::Grayscale {
+            return Some(original.clone());
+        }
+        match self.images.get(&ImageType::Grayscale) {
+            Some(grayscale) if grayscale.get_origin() == (0, 0)
+                && (grayscale.get_image_width(), grayscale.get_image_height()) == (original.get_image_width(), original.get_image_height()) => Some(grayscale.clone()),
+            _ => Some(original.to_grayscale(method)),
+        }
     }
 
 
@@ -249,24 +874,33 @@ impl ImageProcessSample {
             panel_information: PanelInformation::new(),
             image_panel: ImagePanel::new(),
             is_processing: false,
+            progress: Arc::new(ProcessProgress::new()),
+            running_steps: Vec::new(),
         }
     }
 
 
     fn image_load(&mut self) {
-        match self.image_panel.image_load(&self.user_interact_items.path) {
+        match self.image_panel.image_load(&self.user_interact_items.path, self.user_interact_items.grayscale_method) {
             Ok(message) => {
                 self.user_interact_items.is_image_loaded = true;
+                self.user_interact_items.image_size = self.image_panel.images.get(&ImageType::Original)
+                    .map_or((0, 0), |original| (original.get_image_width() as u32, original.get_image_height() as u32));
+                self.user_interact_items.inspectable_steps = 0;
                 self.panel_information.image_load_result = message;
             },
             Err(e) => {
                 self.user_interact_items.is_image_loaded = false;
                 self.panel_information.image_load_result = e.to_string();
-                self.user_interact_items.process_type = ProcessType::None;
+                self.user_interact_items.inspectable_steps = 0;
                 self.panel_information.processed_type = ProcessType::None;
+                self.panel_information.displayed_step = String::from("");
                 self.panel_information.image_process_result = String::from("");
                 self.statics_reset();
                 self.image_panel.images.clear();
+                self.image_panel.displays.clear();
+                self.image_panel.histograms.clear();
+                self.image_panel.statistics.clear();
             }
         
         };
@@ -289,11 +923,169 @@ impl ImageProcessSample {
 
         }
     }
+    fn image_save(&mut self) {
+        let Some(image_panel_data) = self.image_panel.images.get(&ImageType::Processed) else {
+            self.panel_information.image_save_result = String::from("no processed image");
+            return;
+        };
+        if let Some(path) = FileDialog::new()
+            .add_filter("Image", SAVE_FILE_EXTENTIONS)
+            .set_file_name("processed.png")
+            .save_file()
+        {
+            self.panel_information.image_save_result = match path.as_path().to_str() {
+                Some(path) => match image_save(image_panel_data, path, self.user_interact_items.jpeg_quality) {
+                    Ok(message) => message,
+                    Err(e) => e.to_string(),
+                },
+                None => String::from("invalid path"),
+            };
+        }
+    }
+
+    fn recipe_save(&mut self) {
+        if let Some(path) = FileDialog::new()
+            .add_filter("Recipe", RECIPE_FILE_EXTENTIONS)
+            .set_file_name("recipe.toml")
+            .save_file()
+        {
+            self.panel_information.recipe_result = match path.as_path().to_str() {
+                Some(path) => match recipe_save(&self.user_interact_items.pipeline, path) {
+                    Ok(message) => message,
+                    Err(e) => e.to_string(),
+                },
+                None => String::from("invalid path"),
+            };
+        }
+    }
+
+    fn recipe_load(&mut self) {
+        if let Some(path) = FileDialog::new()
+            .add_filter("Recipe", RECIPE_FILE_EXTENTIONS)
+            .pick_file()
+        {
+            let result = match path.as_path().to_str() {
+                Some(path) => recipe_load(path).map_err(|e| e.to_string()),
+                None => Err(String::from("invalid path")),
+            };
+            match result {
+                Ok(pipeline) => {
+                    self.user_interact_items.pipeline = pipeline;
+                    self.user_interact_items.selected_step = None;
+                    self.user_interact_items.inspectable_steps = 0;
+                    self.panel_information.recipe_result = String::from("OK");
+                },
+                Err(message) => {
+                    self.panel_information.recipe_result = message;
+                }
+            }
+        }
+    }
+
+    /// Sets the selected crop step to the box around the ROI.
+    fn crop_to_roi(&mut self) {
+        let items = &mut self.user_interact_items;
+        let (Some(index), Some(roi)) = (items.selected_step, &self.image_panel.roi) else {
+            return;
+        };
+        if !matches!(items.pipeline[index], ProcessParameters::Crop(_)) {
+            return;
+        }
+        // The ROI is drawn in loaded-image coordinates, the crop is relative to its input.
+        let ((input_left, input_top), (width, height)) = items.step_input_area(index);
+        let (left, top, right, bottom) = roi.translated(-(input_left as f32), -(input_top as f32)).bounds();
+        let (x, y) = (left.max(0.0).floor() as u32, top.max(0.0).floor() as u32);
+        if x >= width || y >= height {
+            return;
+        }
+        let right = (right.ceil().max(0.0) as u32).min(width);
+        let bottom = (bottom.ceil().max(0.0) as u32).min(height);
+        items.pipeline[index] = ProcessParameters::Crop(CropParameters {
+            x,
+            y,
+            width: right.saturating_sub(x).max(1),
+            height: bottom.saturating_sub(y).max(1),
+        });
+        items.inspectable_steps = 0;
+    }
+
+    fn move_step(&mut self, from: usize, to: usize) {
+        self.user_interact_items.pipeline.swap(from, to);
+        self.user_interact_items.inspectable_steps = 0;
+        self.user_interact_items.selected_step = match self.user_interact_items.selected_step {
+            Some(selected) if selected == from => Some(to),
+            Some(selected) if selected == to => Some(from),
+            selected => selected,
+        };
+    }
+
+    fn show_step_result(&mut self, index: usize) {
+        let image_panel_data = self.image_panel.step_results[index].clone();
+        self.panel_information.processed_type = self.image_panel.processed_steps[index].process_type();
+        self.panel_information.displayed_step = format!("{} of {}", index + 1, self.image_panel.step_results.len());
+        self.panel_information.threshold_value = match image_panel_data.get_threshold() {
+            Some(threshold) => PanelInfoImageValueState::Set(threshold as usize),
+            None => PanelInfoImageValueState::Unset,
+        };
+        self.image_panel.images.insert(ImageType::Processed, image_panel_data);
+        self.image_panel.refresh_display(ImageType::Processed);
+        self.image_panel.grayscale_follow(self.user_interact_items.grayscale_method);
+        self.pixel_inspect();
+    }
+
+    fn progress_row(&self) -> Row<'_, Message> {
+        let percent = self.progress.fraction() * 100.0;
+        let cancel_button = match self.progress.is_cancelled() {
+            true => Button::new(Text::new("Cancelling...")),
+            false => Button::new(Text::new("Cancel")).on_press(Message::CancelProcess),
+        };
+        Row::new()
+            .spacing(10)
+            .padding(5)
+            .align_items(Alignment::Center)
+            .push(progress_bar(0.0..=100.0, percent).height(Length::Fixed(20.0)))
+            .push(Text::new(format!("{:.0}%", percent)).width(Length::Fixed(50.0)))
+            .push(cancel_button)
+    }
+
+    /// Shows the values of every panel at the pixel under the cursor. The
+    /// position is that in the loaded image, so that it is the same pixel in
+    /// a cropped panel.
+    fn pixel_inspect(&mut self) {
+        fn value_text(image_panel_data: Option<&ImagePanelData>, x: u32, y: u32, as_rgba: bool) -> String {
+            let Some(image_panel_data) = image_panel_data else {
+                return String::from("");
+            };
+            let (left, top) = image_panel_data.get_origin();
+            let (Some(x), Some(y)) = (x.checked_sub(left), y.checked_sub(top)) else {
+                return String::from("");
+            };
+            let has_color = image_panel_data.get_image().color().has_color();
+            match image_panel_data.get_pixel_value(x, y) {
+                Some([red, green, blue, alpha]) if as_rgba || has_color => format!("({}, {}, {}, {})", red, green, blue, alpha),
+                Some([gray, ..]) => gray.to_string(),
+                None => String::from(""),
+            }
+        }
+        let information = &mut self.panel_information;
+        let Some((image_type, (x, y))) = self.image_panel.hovered_pixel else {
+            information.cursor_position = String::from("");
+            information.original_value = String::from("");
+            information.gray_value = String::from("");
+            information.processed_value = String::from("");
+            return;
+        };
+        let (left, top) = self.image_panel.origin(image_type);
+        let (x, y) = (x + left, y + top);
+        let images = &self.image_panel.images;
+        information.cursor_position = format!("({}, {})", x, y);
+        information.original_value = value_text(images.get(&ImageType::Original), x, y, true);
+        information.gray_value = value_text(images.get(&ImageType::Grayscale), x, y, false);
+        information.processed_value = value_text(images.get(&ImageType::Processed), x, y, false);
+    }
+
     fn statics_reset(&mut self) {
-        self.panel_information.image_width = PanelInfoImageValueState::Unset;
-        self.panel_information.image_height = PanelInfoImageValueState::Unset;
-        self.panel_information.max_image_value = PanelInfoImageValueState::Unset;
-        self.panel_information.min_image_value = PanelInfoImageValueState::Unset;
+        self.panel_information.threshold_value = PanelInfoImageValueState::Unset;
     }
 }
 
@@ -313,27 +1105,27 @@ impl Application for ImageProcessSample {
     }
     fn view(&self) -> iced::Element<'_, Self::Message> {
         
-        let grid = Column::new().spacing(0).align_items(Alignment::Center)
+        let mut grid = Column::new().spacing(0).align_items(Alignment::Center);
+        if self.is_processing {
+            grid = grid.push(self.progress_row());
+        }
+        let grid = grid
             .push(self.user_interact_items.to_row())
             .push(self.panel_information.to_row())
             .push(self.image_panel.to_row());
 
-        let content = container(grid)
+        container(grid)
             .width(Length::Fill)
             .height(Length::Fill)
             .center_x()
-            .center_y();
-        if self.is_processing{
-            let processing_text = Container::new(Text::new("Processing...").size(50))
-                .width(Length::Fill)
-                .height(Length::Fill)
-                .center_x()
-                .center_y();
-            
-                processing_text.into()
+            .center_y()
+            .into()
+    }
+    fn subscription(&self) -> Subscription<Self::Message> {
+        if self.is_processing {
+            progress_ticks()
         } else {
-            content.into()
-        
+            Subscription::none()
         }
     }
     fn update(&mut self, message: Self::Message) -> Command<Self::Message> {
@@ -343,39 +1135,188 @@ impl Application for ImageProcessSample {
                 self.user_interact_items.process_type = process_type;
                 Command::none()
             },
+            Message::GrayscaleMethodSelected(method) => {
+                if !self.is_processing {
+                    self.user_interact_items.grayscale_method = method;
+                    self.image_panel.grayscale_update(method);
+                }
+                Command::none()
+            },
+            Message::ColorModeSelected(color_mode) => {
+                self.user_interact_items.color_mode = color_mode;
+                Command::none()
+            },
+            Message::ProcessParametersChanged(process_parameters) => {
+                if let Some(index) = self.user_interact_items.selected_step {
+                    self.user_interact_items.pipeline[index] = process_parameters;
+                    self.user_interact_items.inspectable_steps = 0;
+                }
+                self.user_interact_items.parameter_text = None;
+                Command::none()
+            },
+            Message::ParameterTextChanged(caption, text, process_parameters) => {
+                if let Some(index) = self.user_interact_items.selected_step {
+                    if let Some(process_parameters) = process_parameters {
+                        self.user_interact_items.pipeline[index] = process_parameters;
+                        self.user_interact_items.inspectable_steps = 0;
+                    }
+                    self.user_interact_items.parameter_text = Some((index, caption, text));
+                }
+                Command::none()
+            },
+            Message::AddStep => {
+                let pipeline = &mut self.user_interact_items.pipeline;
+                pipeline.push(self.user_interact_items.process_type.default_parameters());
+                self.user_interact_items.selected_step = Some(pipeline.len() - 1);
+                self.user_interact_items.inspectable_steps = 0;
+                Command::none()
+            },
+            Message::RemoveStep(index) => {
+                self.user_interact_items.pipeline.remove(index);
+                self.user_interact_items.inspectable_steps = 0;
+                self.user_interact_items.selected_step = match self.user_interact_items.selected_step {
+                    Some(selected) if selected == index => None,
+                    Some(selected) if selected > index => Some(selected - 1),
+                    selected => selected,
+                };
+                Command::none()
+            },
+            Message::MoveStepUp(index) => {
+                self.move_step(index, index - 1);
+                Command::none()
+            },
+            Message::MoveStepDown(index) => {
+                self.move_step(index, index + 1);
+                Command::none()
+            },
+            Message::SelectStep(index) => {
+                self.user_interact_items.selected_step = Some(index);
+                Command::none()
+            },
+            Message::InspectStep(index) => {
+                self.show_step_result(index);
+                Command::none()
+            },
+            Message::PanelViewChanged(image_type, view) => {
+                self.image_panel.set_view(image_type, view);
+                Command::none()
+            },
+            Message::PixelHovered(image_type, position) => {
+                match position {
+                    Some(position) => self.image_panel.hovered_pixel = Some((image_type, position)),
+                    // The cursor may already have entered another panel.
+                    None if self.image_panel.hovered_pixel.map(|(hovered_type, _)| hovered_type) == Some(image_type) => {
+                        self.image_panel.hovered_pixel = None;
+                    },
+                    None => {},
+                }
+                self.pixel_inspect();
+                Command::none()
+            },
+            Message::LockViewsToggled(lock_views) => {
+                self.image_panel.lock_views = lock_views;
+                Command::none()
+            },
+            Message::HistogramLogToggled(histogram_log) => {
+                self.image_panel.histogram_log = histogram_log;
+                Command::none()
+            },
+            Message::RoiToolSelected(roi_tool) => {
+                self.image_panel.roi_tool = roi_tool;
+                Command::none()
+            },
+            Message::RoiDrawn(image_type, roi) => {
+                let (left, top) = self.image_panel.origin(image_type);
+                self.image_panel.set_roi(Some(roi.translated(left as f32, top as f32)));
+                Command::none()
+            },
+            Message::CropToRoi => {
+                self.crop_to_roi();
+                Command::none()
+            },
+            Message::ClearRoi => {
+                self.image_panel.set_roi(None);
+                Command::none()
+            },
+            Message::ProcessInRoiToggled(process_in_roi) => {
+                self.image_panel.process_in_roi = process_in_roi;
+                Command::none()
+            },
+            Message::PercentilesChanged(text) => {
+                if let Some(percentiles) = parse_percentiles(&text) {
+                    self.image_panel.percentiles = percentiles;
+                }
+                self.image_panel.percentiles_text = text;
+                Command::none()
+            },
             Message::PathChanged(path) => {self.user_interact_items.path = path; Command::none()},
-            Message::ImageLoad => {self.image_load();  Command::none()},
+            Message::ImageLoad => {
+                if !self.is_processing {
+                    self.image_load();
+                }
+                Command::none()
+            },
             Message::ShowFileDialog => {self.file_path_select(); Command::none()},
-            Message::Process => {
-                if self.user_interact_items.is_image_loaded {
+            Message::SaveImage => {self.image_save(); Command::none()},
+            Message::JpegQualityChanged(jpeg_quality) => {self.user_interact_items.jpeg_quality = jpeg_quality; Command::none()},
+            Message::ThreadCountChanged(thread_count) => {
+                self.user_interact_items.thread_count = thread_count;
+                set_thread_count(thread_count as usize);
+                Command::none()
+            },
+            Message::SaveRecipe => {self.recipe_save(); Command::none()},
+            Message::LoadRecipe => {self.recipe_load(); Command::none()},
+            Message::Process(pipeline) => {
+                if self.user_interact_items.is_image_loaded && !self.is_processing {
                     self.is_processing = true;
-                    Command::perform(process_image(
-                        self.image_panel.images.get(&ImageType::Grayscale).unwrap().clone(), 
-                        self.user_interact_items.process_type), Message::ProcessEnd)
+                    self.progress = Arc::new(ProcessProgress::new());
+                    self.running_steps = pipeline.clone();
+                    let color_mode = self.user_interact_items.color_mode;
+                    let input = self.image_panel.pipeline_input(color_mode, self.user_interact_items.grayscale_method).unwrap();
+                    let roi = self.image_panel.roi.clone().filter(|_| self.image_panel.process_in_roi);
+                    let progress = self.progress.clone();
+                    Command::perform(async move {
+                        let results = process_color_pipeline(input.clone(), pipeline, color_mode, progress).await?;
+                        match roi {
+                            Some(roi) => results.iter().map(|result| restrict_to_roi(&input, result, &roi)).collect(),
+                            None => Ok(results),
+                        }
+                    }, Message::ProcessEnd)
                 } else {
                     Command::none()
                 }
 
             },
+            Message::CancelProcess => {
+                self.progress.cancel();
+                Command::none()
+            },
+            Message::ProgressTick => Command::none(),
             Message::ProcessEnd(result) => {
                 self.is_processing = false;
                 match result {
-                    Ok(image_panel_data) => {
-                        self.image_panel.images.insert(ImageType::Processed, image_panel_data);
+                    Ok(step_results) => {
+                        let processed_steps = std::mem::take(&mut self.running_steps);
+                        // The pipeline may have been edited while it ran.
+                        self.user_interact_items.inspectable_steps = match processed_steps == self.user_interact_items.pipeline {
+                            true => step_results.len(),
+                            false => 0,
+                        };
+                        self.image_panel.processed_steps = processed_steps;
+                        self.image_panel.step_results = step_results;
                         self.panel_information.image_process_result = String::from("OK");
-                        self.panel_information.processed_type = self.user_interact_items.process_type;
-                        self.panel_information.image_width = PanelInfoImageValueState::Set(self.image_panel.images[&ImageType::Processed].get_image_width());
-                        self.panel_information.image_height = PanelInfoImageValueState::Set(self.image_panel.images[&ImageType::Processed].get_image_height());
-                        self.panel_information.max_image_value = PanelInfoImageValueState::Set(self.image_panel.images[&ImageType::Processed].get_max_image_value());
-                        self.panel_information.min_image_value = PanelInfoImageValueState::Set(self.image_panel.images[&ImageType::Processed].get_min_image_value());
+                        self.show_step_result(self.image_panel.step_results.len() - 1);
+                    },
+                    Err(e) if self.progress.is_cancelled() => {
+                        self.panel_information.image_process_result = e.message;
                     },
                     Err(e) => {
                         self.panel_information.processed_type = ProcessType::None;
+                        self.panel_information.displayed_step = String::from("");
                         self.panel_information.image_process_result = e.message;
-                        self.panel_information.image_width = PanelInfoImageValueState::Unset;
-                        self.panel_information.image_height = PanelInfoImageValueState::Unset;
-                        self.panel_information.max_image_value = PanelInfoImageValueState::Unset;
-                        self.panel_information.min_image_value = PanelInfoImageValueState::Unset;
+                        self.user_interact_items.inspectable_steps = 0;
+                        self.image_panel.step_results.clear();
+                        self.statics_reset();
                     }
                 }
                 Command::none()
@@ -386,3 +1327,25 @@ impl Application for ImageProcessSample {
 
 }
 
+/// Ticks while a pipeline runs so that the progress bar is redrawn.
+fn progress_ticks() -> Subscription<Message> {
+    struct ProgressTicks;
+    subscription::unfold(
+        std::any::TypeId::of::<ProgressTicks>(),
+        None,
+        |receiver: Option<mpsc::UnboundedReceiver<()>>| async move {
+            let mut receiver = receiver.unwrap_or_else(|| {
+                let (sender, receiver) = mpsc::unbounded();
+                // Stops once the subscription is dropped along with the receiver.
+                thread::spawn(move || {
+                    while sender.unbounded_send(()).is_ok() {
+                        thread::sleep(PROGRESS_REFRESH_INTERVAL);
+                    }
+                });
+                receiver
+            });
+            receiver.next().await;
+            (Message::ProgressTick, Some(receiver))
+        },
+    )
+}
@@ -0,0 +1,158 @@
+//! Row-parallel execution of the processing operations.
+//!
+//! Every operation computes each output row independently of the others, so
+//! splitting the rows across threads gives the same result as the serial path.
+//! The helpers report every finished row to a `ProcessProgress` and skip the
+//! remaining rows once it is cancelled.
+
+use std::sync::{Arc, Mutex};
+
+use image_crate::{ImageBuffer, Pixel, Primitive};
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
+
+use crate::progress::ProcessProgress;
+
+/// Thread count requested with `set_thread_count` and the pool built for it.
+static THREAD_POOL: Mutex<(usize, Option<Arc<ThreadPool>>)> = Mutex::new((0, None));
+
+/// Sets the number of threads the operations use; 0 uses all CPU cores.
+pub fn set_thread_count(threads: usize) {
+    let mut thread_pool = THREAD_POOL.lock().unwrap();
+    if thread_pool.0 != threads {
+        *thread_pool = (threads, None);
+    }
+}
+
+pub fn thread_count() -> usize {
+    THREAD_POOL.lock().unwrap().0
+}
+
+fn thread_pool() -> Arc<ThreadPool> {
+    let mut thread_pool = THREAD_POOL.lock().unwrap();
+    let threads = thread_pool.0;
+    thread_pool.1.get_or_insert_with(|| {
+        Arc::new(ThreadPoolBuilder::new().num_threads(threads).build().expect("failed to start worker threads"))
+    }).clone()
+}
+
+/// Runs `op` on the configured thread pool.
+pub(crate) fn install<R, F>(op: F) -> R
+where
+R: Send,
+F: FnOnce() -> R + Send,
+{
+    thread_pool().install(op)
+}
+
+/// Parallel counterpart of `ImageBuffer::from_fn`.
+pub(crate) fn from_fn<P, F>(width: u32, height: u32, progress: &ProcessProgress, f: F) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+P: Pixel + Send + Sync,
+P::Subpixel: Send + Sync,
+F: Fn(u32, u32) -> P + Sync,
+{
+    let channels = P::CHANNEL_COUNT as usize;
+    let mut data = vec![P::Subpixel::DEFAULT_MIN_VALUE; width as usize * height as usize * channels];
+    if !data.is_empty() {
+        progress.begin_pass(height as usize);
+        install(|| {
+            data.par_chunks_mut(width as usize * channels).enumerate().for_each(|(y, row)| {
+                if progress.is_cancelled() {
+                    return;
+                }
+                for (x, pixel) in row.chunks_mut(channels).enumerate() {
+                    pixel.copy_from_slice(f(x as u32, y as u32).channels());
+                }
+                progress.row_done();
+            });
+        });
+        progress.end_pass();
+    }
+    ImageBuffer::from_raw(width, height, data).unwrap()
+}
+
+/// Fills `data`, made of rows of `row_length` values, by calling `f` with the
+/// index and contents of every row.
+pub(crate) fn for_each_row<T, F>(data: &mut [T], row_length: usize, progress: &ProcessProgress, f: F)
+where
+T: Send,
+F: Fn(usize, &mut [T]) + Sync,
+{
+    if row_length == 0 {
+        return;
+    }
+    progress.begin_pass(data.len() / row_length);
+    install(|| {
+        data.par_chunks_mut(row_length).enumerate().for_each(|(y, row)| {
+            if !progress.is_cancelled() {
+                f(y, row);
+                progress.row_done();
+            }
+        });
+    });
+    progress.end_pass();
+}
+
+/// Like `for_each_row`, with per-thread scratch state created by `init`.
+pub(crate) fn for_each_row_init<T, S, I, F>(data: &mut [T], row_length: usize, progress: &ProcessProgress, init: I, f: F)
+where
+T: Send,
+I: Fn() -> S + Sync,
+F: Fn(&mut S, usize, &mut [T]) + Sync,
+{
+    if row_length == 0 {
+        return;
+    }
+    progress.begin_pass(data.len() / row_length);
+    install(|| {
+        data.par_chunks_mut(row_length).enumerate().for_each_init(&init, |state, (y, row)| {
+            if !progress.is_cancelled() {
+                f(state, y, row);
+                progress.row_done();
+            }
+        });
+    });
+    progress.end_pass();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image_crate::DynamicImage;
+
+    use crate::color::{process_color_pipeline, ColorMode};
+    use crate::process::{ImagePanelData, ProcessType};
+    use crate::test_util::test_image;
+
+    /// Output bytes of every step of every default pipeline, in both color modes.
+    fn run_all() -> Vec<Vec<u8>> {
+        let luma = test_image(40, 30);
+        let value = |x, y| luma.get_pixel(x, y)[0];
+        let color = DynamicImage::ImageRgb16(ImageBuffer::from_fn(40, 30, |x, y| {
+            image_crate::Rgb([value(x, y), value(39 - x, y), value(x, 29 - y)])
+        }));
+        let gray = DynamicImage::ImageLuma16(luma.clone());
+        let mut outputs = Vec::new();
+        for (image, mode) in [(gray, ColorMode::Grayscale), (color, ColorMode::Channels)] {
+            for process_type in ProcessType::ALL {
+                let steps = vec![ProcessType::GaussianBlur.default_parameters(), process_type.default_parameters()];
+                let input = ImagePanelData::new(image.clone());
+                let results = pollster::block_on(process_color_pipeline(input, steps, mode, Arc::new(ProcessProgress::new())))
+                    .unwrap_or_else(|e| panic!("{}: {}", process_type, e.message));
+                outputs.extend(results.iter().map(|result| result.get_image().as_bytes().to_vec()));
+            }
+        }
+        outputs
+    }
+
+    #[test]
+    fn results_do_not_depend_on_the_thread_count() {
+        set_thread_count(1);
+        let serial = run_all();
+        set_thread_count(4);
+        let parallel = run_all();
+        set_thread_count(0);
+        assert_eq!(serial.len(), parallel.len());
+        assert!(serial == parallel);
+    }
+}
@@ -1,67 +1,561 @@
-extern crate image as image_crate;
 use std::{
-    collections::HashMap, error::Error, fmt, hash, path::Path, sync::Arc
+    collections::HashMap, error::Error, fmt, fs::File, hash, io::BufWriter, path::Path, sync::Arc
 };
 
 use image_crate::{
-    io::Reader as ImageReader, DynamicImage, ImageBuffer, Pixel, Rgba
+    codecs::jpeg::JpegEncoder, io::Reader as ImageReader, DynamicImage, ImageBuffer, ImageFormat, Luma, Pixel
 };
-use iced::widget::image::Handle;
+use serde::{Deserialize, Serialize};
 
+use crate::edge;
+use crate::filter;
+use crate::integral::IntegralImage;
+use crate::parallel;
+use crate::progress::ProcessProgress;
 
-const THRESHOLD: u16 = 65535 / 2;
-const MAX_COLOR: u8 = 255;
-const FILTER_SIZE: i128 = 11;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub type LumaBuffer = ImageBuffer<Luma<u16>, Vec<u16>>;
+
+const DEFAULT_THRESHOLD: u16 = 65535 / 2;
+pub(crate) const MAX_COLOR: u8 = 255;
+const DEFAULT_FILTER_SIZE: u32 = 11;
+const DEFAULT_ADAPTIVE_WINDOW_SIZE: u32 = 15;
+const DEFAULT_ADAPTIVE_OFFSET: f64 = 1280.0;
+const DEFAULT_SAUVOLA_K: f64 = 0.34;
+const SAUVOLA_DYNAMIC_RANGE: f64 = 32768.0;
+const DEFAULT_NIBLACK_K: f64 = -0.2;
+const DEFAULT_GAUSSIAN_SIGMA: f64 = 2.0;
+const DEFAULT_RANK_WINDOW_SIZE: u32 = 5;
+const DEFAULT_RANK_PERCENTILE: f64 = 25.0;
+const DEFAULT_EDGE_CLIP_PERCENTILE: f64 = 99.0;
+const DEFAULT_CANNY_SIGMA: f64 = 1.4;
+const DEFAULT_CANNY_LOW_THRESHOLD: u16 = 20 * 257;
+const DEFAULT_CANNY_HIGH_THRESHOLD: u16 = 50 * 257;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, hash::Hash)]
 pub enum ProcessType {
     #[default]
     None,
+    Crop,
     Binarization,
+    OtsuBinarization,
+    AdaptiveBinarization(AdaptiveMethod),
     ConvolveFilterAVG,
+    GaussianBlur,
+    RankFilter(RankKind),
+    EdgeDetection(EdgeOperator),
+    Canny,
 }
 impl ProcessType {
     pub const ALL: &'static [Self] = &[
         Self::None,
+        Self::Crop,
         Self::Binarization,
+        Self::OtsuBinarization,
+        Self::AdaptiveBinarization(AdaptiveMethod::Mean),
+        Self::AdaptiveBinarization(AdaptiveMethod::Gaussian),
+        Self::AdaptiveBinarization(AdaptiveMethod::Sauvola),
+        Self::AdaptiveBinarization(AdaptiveMethod::Niblack),
         Self::ConvolveFilterAVG,
+        Self::GaussianBlur,
+        Self::RankFilter(RankKind::Median),
+        Self::RankFilter(RankKind::Minimum),
+        Self::RankFilter(RankKind::Maximum),
+        Self::RankFilter(RankKind::Percentile),
+        Self::EdgeDetection(EdgeOperator::Sobel),
+        Self::EdgeDetection(EdgeOperator::Prewitt),
+        Self::EdgeDetection(EdgeOperator::Scharr),
+        Self::Canny,
     ];
 }
 impl fmt::Display for ProcessType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ProcessType::None => write!(f, "None"),
+            ProcessType::Crop => write!(f, "Crop"),
             ProcessType::Binarization => write!(f, "Binarize"),
+            ProcessType::OtsuBinarization => write!(f, "Binarize (Otsu)"),
+            ProcessType::AdaptiveBinarization(method) => write!(f, "Binarize (adaptive {})", method),
             ProcessType::ConvolveFilterAVG => write!(f, "AVG filter"),
+            ProcessType::GaussianBlur => write!(f, "Gaussian blur"),
+            ProcessType::RankFilter(rank) => write!(f, "{} filter", rank),
+            ProcessType::EdgeDetection(operator) => write!(f, "{} edges", operator),
+            ProcessType::Canny => write!(f, "Canny edges"),
+        }
+    }
+}
+impl ProcessType {
+    pub fn default_parameters(&self) -> ProcessParameters {
+        match self {
+            ProcessType::None => ProcessParameters::None,
+            ProcessType::Crop => ProcessParameters::Crop(CropParameters::default()),
+            ProcessType::Binarization => ProcessParameters::Binarization(BinarizationParameters::default()),
+            ProcessType::OtsuBinarization => ProcessParameters::OtsuBinarization(OtsuParameters::default()),
+            ProcessType::AdaptiveBinarization(method) => ProcessParameters::AdaptiveBinarization { method: *method, parameters: AdaptiveParameters::default_for(*method) },
+            ProcessType::ConvolveFilterAVG => ProcessParameters::ConvolveFilterAVG(FilterParameters::default()),
+            ProcessType::GaussianBlur => ProcessParameters::GaussianBlur(GaussianParameters::default()),
+            ProcessType::RankFilter(rank) => ProcessParameters::RankFilter { rank: *rank, parameters: RankParameters::default() },
+            ProcessType::EdgeDetection(operator) => ProcessParameters::EdgeDetection { operator: *operator, parameters: EdgeParameters::default() },
+            ProcessType::Canny => ProcessParameters::Canny(CannyParameters::default()),
+        }
+    }
+}
+
+/// How the Grayscale image is derived from the color channels of the Original.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, hash::Hash, Serialize, Deserialize)]
+pub enum GrayscaleMethod {
+    Rec601,
+    #[default]
+    Rec709,
+    Average,
+    Lightness,
+    Red,
+    Green,
+    Blue,
+}
+impl GrayscaleMethod {
+    pub const ALL: &'static [Self] = &[
+        Self::Rec601,
+        Self::Rec709,
+        Self::Average,
+        Self::Lightness,
+        Self::Red,
+        Self::Green,
+        Self::Blue,
+    ];
+
+    /// Gray value of a 16-bit RGB pixel.
+    pub fn luma(&self, red: u16, green: u16, blue: u16) -> u16 {
+        let (red, green, blue) = (red as f64, green as f64, blue as f64);
+        let value = match self {
+            GrayscaleMethod::Rec601 => 0.299 * red + 0.587 * green + 0.114 * blue,
+            GrayscaleMethod::Rec709 => 0.2126 * red + 0.7152 * green + 0.0722 * blue,
+            GrayscaleMethod::Average => (red + green + blue) / 3.0,
+            GrayscaleMethod::Lightness => (red.max(green).max(blue) + red.min(green).min(blue)) / 2.0,
+            GrayscaleMethod::Red => red,
+            GrayscaleMethod::Green => green,
+            GrayscaleMethod::Blue => blue,
+        };
+        value.round() as u16
+    }
+}
+impl fmt::Display for GrayscaleMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrayscaleMethod::Rec601 => write!(f, "Rec.601"),
+            GrayscaleMethod::Rec709 => write!(f, "Rec.709"),
+            GrayscaleMethod::Average => write!(f, "Average"),
+            GrayscaleMethod::Lightness => write!(f, "Lightness"),
+            GrayscaleMethod::Red => write!(f, "Red channel"),
+            GrayscaleMethod::Green => write!(f, "Green channel"),
+            GrayscaleMethod::Blue => write!(f, "Blue channel"),
+        }
+    }
+}
+
+/// How the local threshold of the adaptive binarization is derived from the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, hash::Hash, Serialize, Deserialize)]
+pub enum AdaptiveMethod {
+    Mean,
+    Gaussian,
+    Sauvola,
+    Niblack,
+}
+impl fmt::Display for AdaptiveMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdaptiveMethod::Mean => write!(f, "mean"),
+            AdaptiveMethod::Gaussian => write!(f, "Gaussian"),
+            AdaptiveMethod::Sauvola => write!(f, "Sauvola"),
+            AdaptiveMethod::Niblack => write!(f, "Niblack"),
         }
     }
 }
 
+/// Rectangle kept by a crop, clamped to the image. A width or height of 0
+/// reaches to the right or bottom edge.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct CropParameters {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+impl CropParameters {
+    /// The `(x, y, width, height)` kept of a `width` x `height` image, or
+    /// `None` when the rectangle starts outside it.
+    pub fn rectangle(&self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        if self.x >= width || self.y >= height {
+            return None;
+        }
+        let kept_width = match self.width {
+            0 => width - self.x,
+            kept_width => kept_width.min(width - self.x),
+        };
+        let kept_height = match self.height {
+            0 => height - self.y,
+            kept_height => kept_height.min(height - self.y),
+        };
+        Some((self.x, self.y, kept_width, kept_height))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BinarizationParameters {
+    pub threshold: u16,
+    pub max_color: u8,
+}
+impl Default for BinarizationParameters {
+    fn default() -> Self {
+        BinarizationParameters { threshold: DEFAULT_THRESHOLD, max_color: MAX_COLOR }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct OtsuParameters {
+    pub max_color: u8,
+}
+impl Default for OtsuParameters {
+    fn default() -> Self {
+        OtsuParameters { max_color: MAX_COLOR }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AdaptiveParameters {
+    /// Side of the square window; even sizes are rounded up to the next odd size.
+    pub window_size: u32,
+    /// Subtracted from the local threshold, in 16-bit units.
+    pub offset: f64,
+    /// Weight of the local standard deviation (Sauvola and Niblack only).
+    pub k: f64,
+    pub max_color: u8,
+    #[serde(default)]
+    pub border: BorderMode,
+}
+impl AdaptiveParameters {
+    pub fn default_for(method: AdaptiveMethod) -> Self {
+        let k = match method {
+            AdaptiveMethod::Niblack => DEFAULT_NIBLACK_K,
+            _ => DEFAULT_SAUVOLA_K,
+        };
+        AdaptiveParameters {
+            window_size: DEFAULT_ADAPTIVE_WINDOW_SIZE,
+            offset: DEFAULT_ADAPTIVE_OFFSET,
+            k,
+            max_color: MAX_COLOR,
+            border: BorderMode::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FilterParameters {
+    /// Side of the square window; even sizes are rounded up to the next odd size.
+    pub filter_size: u32,
+    #[serde(default)]
+    pub border: BorderMode,
+}
+impl Default for FilterParameters {
+    fn default() -> Self {
+        FilterParameters { filter_size: DEFAULT_FILTER_SIZE, border: BorderMode::default() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GaussianParameters {
+    pub sigma: f64,
+    /// Number of taps of each 1-D pass; 0 derives it from sigma (6 sigma + 1).
+    pub kernel_size: u32,
+    #[serde(default)]
+    pub border: BorderMode,
+}
+impl Default for GaussianParameters {
+    fn default() -> Self {
+        GaussianParameters { sigma: DEFAULT_GAUSSIAN_SIGMA, kernel_size: 0, border: BorderMode::default() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RankParameters {
+    /// Side (or diameter) of the window; even sizes are rounded up to the next odd size.
+    pub window_size: u32,
+    pub shape: WindowShape,
+    /// Percentile (0-100) kept by `RankKind::Percentile`.
+    pub percentile: f64,
+    #[serde(default)]
+    pub border: BorderMode,
+}
+impl Default for RankParameters {
+    fn default() -> Self {
+        RankParameters {
+            window_size: DEFAULT_RANK_WINDOW_SIZE,
+            shape: WindowShape::default(),
+            percentile: DEFAULT_RANK_PERCENTILE,
+            border: BorderMode::default(),
+        }
+    }
+}
+
+/// 3x3 gradient kernel of the edge detection; they differ in how they smooth
+/// across the direction of the derivative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, hash::Hash, Serialize, Deserialize)]
+pub enum EdgeOperator {
+    Sobel,
+    Prewitt,
+    Scharr,
+}
+impl fmt::Display for EdgeOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EdgeOperator::Sobel => write!(f, "Sobel"),
+            EdgeOperator::Prewitt => write!(f, "Prewitt"),
+            EdgeOperator::Scharr => write!(f, "Scharr"),
+        }
+    }
+}
+
+/// What an edge detection step stores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum EdgeOutput {
+    /// The gradient magnitude as a gray image.
+    #[default]
+    Magnitude,
+    /// The gradient direction as hue, with the magnitude as brightness.
+    Direction,
+}
+impl EdgeOutput {
+    pub const ALL: [EdgeOutput; 2] = [EdgeOutput::Magnitude, EdgeOutput::Direction];
+}
+impl fmt::Display for EdgeOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EdgeOutput::Magnitude => write!(f, "Magnitude"),
+            EdgeOutput::Direction => write!(f, "Direction (HSV)"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EdgeParameters {
+    pub output: EdgeOutput,
+    /// Stretches the magnitude so that `clip_percentile` reaches white; off, it
+    /// is scaled by the largest magnitude the operator can give.
+    pub normalize: bool,
+    /// Percentile (0-100) of the nonzero magnitudes mapped to white; stronger edges saturate.
+    pub clip_percentile: f64,
+    /// `NormalizeValid` reads the nearest edge pixel, as `Replicate` does.
+    #[serde(default = "edge_default_border")]
+    pub border: BorderMode,
+}
+impl Default for EdgeParameters {
+    fn default() -> Self {
+        EdgeParameters {
+            output: EdgeOutput::default(),
+            normalize: true,
+            clip_percentile: DEFAULT_EDGE_CLIP_PERCENTILE,
+            border: edge_default_border(),
+        }
+    }
+}
+fn edge_default_border() -> BorderMode {
+    BorderMode::Replicate
+}
+
+/// Thresholds are gradient magnitudes in 16-bit gray levels, about the
+/// contrast of a step edge.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CannyParameters {
+    /// Sigma of the Gaussian smoothing; 0 skips it.
+    pub sigma: f64,
+    /// Derives the thresholds from the median of the smoothed image, ignoring
+    /// `low_threshold` and `high_threshold`.
+    pub automatic: bool,
+    /// Weaker edges are kept only where they connect to a strong one.
+    pub low_threshold: u16,
+    /// Edges at least this strong are always kept.
+    pub high_threshold: u16,
+    /// `NormalizeValid` reads the nearest edge pixel, as `Replicate` does.
+    #[serde(default = "edge_default_border")]
+    pub border: BorderMode,
+}
+impl Default for CannyParameters {
+    fn default() -> Self {
+        CannyParameters {
+            sigma: DEFAULT_CANNY_SIGMA,
+            automatic: true,
+            low_threshold: DEFAULT_CANNY_LOW_THRESHOLD,
+            high_threshold: DEFAULT_CANNY_HIGH_THRESHOLD,
+            border: edge_default_border(),
+        }
+    }
+}
+
+/// A `ProcessType` together with the values it runs with.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ProcessParameters {
+    #[default]
+    None,
+    Crop(CropParameters),
+    Binarization(BinarizationParameters),
+    OtsuBinarization(OtsuParameters),
+    AdaptiveBinarization { method: AdaptiveMethod, parameters: AdaptiveParameters },
+    ConvolveFilterAVG(FilterParameters),
+    GaussianBlur(GaussianParameters),
+    RankFilter { rank: RankKind, parameters: RankParameters },
+    EdgeDetection { operator: EdgeOperator, parameters: EdgeParameters },
+    Canny(CannyParameters),
+}
+impl ProcessParameters {
+    pub fn process_type(&self) -> ProcessType {
+        match self {
+            ProcessParameters::None => ProcessType::None,
+            ProcessParameters::Crop(_) => ProcessType::Crop,
+            ProcessParameters::Binarization(_) => ProcessType::Binarization,
+            ProcessParameters::OtsuBinarization(_) => ProcessType::OtsuBinarization,
+            ProcessParameters::AdaptiveBinarization { method, .. } => ProcessType::AdaptiveBinarization(*method),
+            ProcessParameters::ConvolveFilterAVG(_) => ProcessType::ConvolveFilterAVG,
+            ProcessParameters::GaussianBlur(_) => ProcessType::GaussianBlur,
+            ProcessParameters::RankFilter { rank, .. } => ProcessType::RankFilter(*rank),
+            ProcessParameters::EdgeDetection { operator, .. } => ProcessType::EdgeDetection(*operator),
+            ProcessParameters::Canny(_) => ProcessType::Canny,
+        }
+    }
+}
+
+/// Which value of the sorted neighborhood a rank filter keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, hash::Hash, Serialize, Deserialize)]
+pub enum RankKind {
+    Median,
+    Minimum,
+    Maximum,
+    Percentile,
+}
+impl fmt::Display for RankKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RankKind::Median => write!(f, "Median"),
+            RankKind::Minimum => write!(f, "Minimum"),
+            RankKind::Maximum => write!(f, "Maximum"),
+            RankKind::Percentile => write!(f, "Percentile"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum WindowShape {
+    #[default]
+    Square,
+    Circle,
+}
+impl WindowShape {
+    pub const ALL: [WindowShape; 2] = [WindowShape::Square, WindowShape::Circle];
+}
+impl fmt::Display for WindowShape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WindowShape::Square => write!(f, "Square"),
+            WindowShape::Circle => write!(f, "Circle"),
+        }
+    }
+}
+
+/// What a neighborhood operation reads for positions outside the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BorderMode {
+    /// A fixed 16-bit value.
+    Constant(u16),
+    /// The nearest edge pixel (`aaa|abcd|ddd`).
+    Replicate,
+    /// The image mirrored at its edge, edge pixel included (`cba|abcd|dcb`).
+    Reflect,
+    /// The opposite side of the image (`bcd|abcd|abc`).
+    Wrap,
+    /// Nothing; the result is normalized by the number of pixels inside the image.
+    #[default]
+    NormalizeValid,
+}
+impl BorderMode {
+    /// The choices offered in the GUI, keeping `constant` as the value of `Constant`.
+    pub fn options(constant: u16) -> [BorderMode; 5] {
+        [
+            BorderMode::Constant(constant),
+            BorderMode::Replicate,
+            BorderMode::Reflect,
+            BorderMode::Wrap,
+            BorderMode::NormalizeValid,
+        ]
+    }
+
+    pub(crate) fn locate(&self, position: i64, length: i64) -> BorderSample {
+        if (0..length).contains(&position) {
+            return BorderSample::Inside(position);
+        }
+        match self {
+            BorderMode::Constant(value) => BorderSample::Constant(*value),
+            BorderMode::Replicate => BorderSample::Inside(position.clamp(0, length - 1)),
+            BorderMode::Reflect => {
+                let position = position.rem_euclid(2 * length);
+                BorderSample::Inside(if position < length { position } else { 2 * length - 1 - position })
+            },
+            BorderMode::Wrap => BorderSample::Inside(position.rem_euclid(length)),
+            BorderMode::NormalizeValid => BorderSample::Skip,
+        }
+    }
+
+    /// Value at (x, y), or `None` if the position is dropped.
+    pub(crate) fn sample(&self, image_buf: &LumaBuffer, x: i64, y: i64) -> Option<u16> {
+        match (self.locate(x, image_buf.width() as i64), self.locate(y, image_buf.height() as i64)) {
+            (BorderSample::Inside(x), BorderSample::Inside(y)) => Some(image_buf.get_pixel(x as u32, y as u32)[0]),
+            (BorderSample::Constant(value), _) | (_, BorderSample::Constant(value)) => Some(value),
+            _ => None,
+        }
+    }
+}
+impl fmt::Display for BorderMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BorderMode::Constant(_) => write!(f, "Constant"),
+            BorderMode::Replicate => write!(f, "Replicate"),
+            BorderMode::Reflect => write!(f, "Reflect"),
+            BorderMode::Wrap => write!(f, "Wrap"),
+            BorderMode::NormalizeValid => write!(f, "Normalize by valid count"),
+        }
+    }
+}
+
+pub(crate) enum BorderSample {
+    Inside(i64),
+    Constant(u16),
+    Skip,
+}
+
 #[derive(Debug, Clone)]
 pub struct ImageProcessError {
     pub message: String,
 }
+impl fmt::Display for ImageProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+impl Error for ImageProcessError {}
 
 #[derive(Debug, Clone)]
 pub struct ImagePanelData {
     pub(crate) image: Arc<DynamicImage>,
+    pub(crate) threshold: Option<u16>,
+    pub(crate) origin: (u32, u32),
 }
 
 impl ImagePanelData {
-    pub fn to_handle(&self) -> iced::widget::image::Handle {
-        create_handle(self.image.clone())
-        
-    }
-    pub fn to_rgba8_image_handle(&self) -> iced::widget::image::Handle {
-        let binding = self.image.clone().to_rgba8();
-        let display_img_buf = ImageBuffer::from_fn(
-            self.image.width(), 
-            self.image.height(), 
-            |x, y| {
-            let pixel = binding.get_pixel(x, y);
-            Rgba([pixel[0], pixel[1], pixel[2], pixel[3]])
-        });
-        Handle::from_pixels(self.image.width(), self.image.height(), display_img_buf.into_raw())
+    pub fn new(image: DynamicImage) -> Self {
+        ImagePanelData {
+            image: Arc::new(image),
+            threshold: None,
+            origin: (0, 0),
+        }
     }
     pub fn get_image_width(&self) -> usize {
         self.image.width() as usize
@@ -71,16 +565,48 @@ impl ImagePanelData {
         self.image.height() as usize
     }
 
-    pub fn get_max_image_value(&self) -> usize {
-        *self.image.to_luma16().iter().max().unwrap() as usize
-    }
-
-    pub fn get_min_image_value(&self) -> usize {
-        *self.image.to_luma16().iter().min().unwrap() as usize
-    }
     pub fn get_image(&self) -> Arc<DynamicImage> {
         self.image.clone()
     }
+    /// Threshold used by the binarization that produced this image, if any.
+    pub fn get_threshold(&self) -> Option<u16> {
+        self.threshold
+    }
+    /// Position of the top-left pixel in the image the pipeline started from;
+    /// crop steps move it.
+    pub fn get_origin(&self) -> (u32, u32) {
+        self.origin
+    }
+    /// RGBA value at (x, y) in the bit depth of the image; float images read as 16-bit.
+    pub fn get_pixel_value(&self, x: u32, y: u32) -> Option<[u16; 4]> {
+        if x >= self.image.width() || y >= self.image.height() {
+            return None;
+        }
+        let pixel = self.image.crop_imm(x, y, 1, 1);
+        let color = self.image.color();
+        Some(match color.bytes_per_pixel() / color.channel_count() {
+            1 => pixel.to_rgba8().get_pixel(0, 0).0.map(u16::from),
+            _ => pixel.to_rgba16().get_pixel(0, 0).0,
+        })
+    }
+    /// The `width` x `height` part of the image from `(x, y)` on, placed where
+    /// that part lies in the image the pipeline started from.
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> ImagePanelData {
+        ImagePanelData {
+            image: Arc::new(self.image.crop_imm(x, y, width, height)),
+            threshold: self.threshold,
+            origin: (self.origin.0 + x, self.origin.1 + y),
+        }
+    }
+    /// Converts the image to 16-bit gray with `method`, ignoring alpha.
+    pub fn to_grayscale(&self, method: GrayscaleMethod) -> ImagePanelData {
+        let rgb_img_buf = self.image.to_rgb16();
+        let gray_img_buf = parallel::from_fn(rgb_img_buf.width(), rgb_img_buf.height(), &ProcessProgress::new(), |x, y| {
+            let [red, green, blue] = rgb_img_buf.get_pixel(x, y).0;
+            Luma([method.luma(red, green, blue)])
+        });
+        ImagePanelData { origin: self.origin, ..ImagePanelData::new(DynamicImage::ImageLuma16(gray_img_buf)) }
+    }
 }
 
 #[derive(Debug, Clone, Copy,Eq, PartialEq, hash::Hash)]
@@ -90,96 +616,326 @@ pub enum ImageType {
     Processed,
 }
 
+/// Largest window a `width` x `height` image can use. Centered anywhere, a
+/// window of twice the longer side holds the whole image; beyond that the
+/// border padding only costs memory.
+pub(crate) fn max_window_size(width: u32, height: u32) -> u64 {
+    2 * width.max(height) as u64 + 1
+}
 
-fn create_handle(dynamic_img: Arc<DynamicImage>) -> iced::widget::image::Handle{
-    let binding = dynamic_img.to_luma16();
-    let display_img_buf = ImageBuffer::from_fn(
-        dynamic_img.width(), 
-        dynamic_img.height(), 
-        |x, y| {
-        let pixel = binding.get_pixel(x, y);
-        Rgba([(pixel[0]/256) as u8, (pixel[0]/256) as u8, (pixel[0]/256) as u8, 255u8])
-    });
-
-    Handle::from_pixels(dynamic_img.width(), dynamic_img.height(), display_img_buf.into_raw())
+/// Fails when a `window_size` wide `name` is larger than `max_window_size`.
+pub(crate) fn check_window_size(name: &str, window_size: u64, width: u32, height: u32) -> Result<(), ImageProcessError> {
+    let limit = max_window_size(width, height);
+    if window_size > limit {
+        return Err(ImageProcessError { message: format!("{} must not exceed {} for a {}x{} image", name, limit, width, height) });
+    }
+    Ok(())
 }
 
-fn create_image_panel_data<P, F, G>(dynamic_img: Arc<DynamicImage>, f: F, g: G) -> Result<ImagePanelData, ImageProcessError> 
+/// Stores the 16-bit gray image made by `g`, which reads the buffer `f` derives
+/// from `dynamic_img`. Results keep their full depth; only display reduces them.
+pub(crate) fn create_image_panel_data<P, F, G>(dynamic_img: Arc<DynamicImage>, progress: &ProcessProgress, f: F, g: G) -> Result<ImagePanelData, ImageProcessError> 
 where
-P: Pixel<Subpixel = u16> + 'static,
+P: Pixel<Subpixel = u16> + Send + Sync + 'static,
 F: Fn(Arc<DynamicImage>) -> Arc<ImageBuffer<P, Vec<P::Subpixel>>>,
-G: Fn(u32, u32, Arc<ImageBuffer<P, Vec<P::Subpixel>>>) -> Rgba<u8>,
+G: Fn(u32, u32, Arc<ImageBuffer<P, Vec<P::Subpixel>>>) -> Luma<u16> + Sync,
 {
     let image_buf = f(dynamic_img.clone());
-    let display_img_buf = ImageBuffer::from_fn(
+    let result_img_buf = parallel::from_fn(
         dynamic_img.width(), 
         dynamic_img.height(), 
+        progress,
         |x, y| {g(x,y, image_buf.clone())});
-    Ok(ImagePanelData {
-        image: Arc::new(DynamicImage::ImageRgba8(display_img_buf))
-    })
+    progress.check()?;
+    Ok(ImagePanelData::new(DynamicImage::ImageLuma16(result_img_buf)))
 }
 
-async fn binarize_image(dynamic_img: Arc<DynamicImage>) -> Result<ImagePanelData, ImageProcessError> {
-    create_image_panel_data(
+/// Scales an 8-bit value to the 16-bit range, mapping 255 to 65535.
+fn to_16_bit(value: u8) -> u16 {
+    value as u16 * 257
+}
+
+fn binarize_with_threshold(dynamic_img: Arc<DynamicImage>, threshold: u16, max_color: u8, progress: &ProcessProgress) -> Result<ImagePanelData, ImageProcessError> {
+    let mut image_panel_data = create_image_panel_data(
         dynamic_img, 
+        progress,
         |dynamic_img: Arc<DynamicImage>|{Arc::new(dynamic_img.to_luma16())},
         |x, y, image_buf| {
         let pixel = image_buf.get_pixel(x, y);
-        if pixel[0] > THRESHOLD {
-            Rgba([MAX_COLOR, MAX_COLOR, MAX_COLOR, MAX_COLOR])
+        if pixel[0] > threshold {
+            Luma([to_16_bit(max_color)])
         } else {
-            Rgba([0, 0, 0, MAX_COLOR])
+            Luma([0])
         }
-    })
+    })?;
+    image_panel_data.threshold = Some(threshold);
+    Ok(image_panel_data)
+}
+
+async fn binarize_image(dynamic_img: Arc<DynamicImage>, parameters: BinarizationParameters, progress: &ProcessProgress) -> Result<ImagePanelData, ImageProcessError> {
+    binarize_with_threshold(dynamic_img, parameters.threshold, parameters.max_color, progress)
+}
+
+/// Picks the 16-bit threshold that maximizes the between-class variance of the
+/// luminance histogram (Otsu's method). A flat image has nothing to split and
+/// gets its own value, so that it turns black rather than white.
+fn otsu_threshold(histogram: &[u64]) -> u16 {
+    let total: u64 = histogram.iter().sum();
+    let sum_all: f64 = histogram.iter().enumerate()
+        .map(|(value, count)| value as f64 * *count as f64)
+        .sum();
+
+    let mut weight_background = 0u64;
+    let mut sum_background = 0f64;
+    let mut max_variance = 0f64;
+    let mut threshold = histogram.iter().position(|count| *count > 0).unwrap_or(0) as u16;
+    for (value, count) in histogram.iter().enumerate() {
+        weight_background += count;
+        if weight_background == 0 {
+            continue;
+        }
+        let weight_foreground = total - weight_background;
+        if weight_foreground == 0 {
+            break;
+        }
+        sum_background += value as f64 * *count as f64;
+        let mean_background = sum_background / weight_background as f64;
+        let mean_foreground = (sum_all - sum_background) / weight_foreground as f64;
+        let variance = weight_background as f64 * weight_foreground as f64
+            * (mean_background - mean_foreground).powi(2);
+        if variance > max_variance {
+            max_variance = variance;
+            threshold = value as u16;
+        }
+    }
+    threshold
+}
+
+async fn otsu_binarize_image(dynamic_img: Arc<DynamicImage>, parameters: OtsuParameters, progress: &ProcessProgress) -> Result<ImagePanelData, ImageProcessError> {
+    let mut histogram = vec![0u64; u16::MAX as usize + 1];
+    for pixel in dynamic_img.to_luma16().iter() {
+        histogram[*pixel as usize] += 1;
+    }
+    binarize_with_threshold(dynamic_img, otsu_threshold(&histogram), parameters.max_color, progress)
+}
+
+fn gaussian_weights(filter_size: i128) -> Vec<f64> {
+    let sigma = 0.3 * ((filter_size - 1) as f64 * 0.5 - 1.0) + 0.8;
+    filter::gaussian_kernel(sigma, filter_size as usize)
 }
 
-async fn convolve_filter_avg(dynamic_img: Arc<DynamicImage>) -> Result<ImagePanelData, ImageProcessError> {
+/// Local statistics an adaptive threshold is computed from.
+enum LocalStatistics {
+    /// Gaussian-weighted mean of every pixel, row by row.
+    Gaussian(Vec<f64>),
+    Integral(IntegralImage),
+}
+
+async fn adaptive_binarize_image(dynamic_img: Arc<DynamicImage>, method: AdaptiveMethod, parameters: AdaptiveParameters, progress: &ProcessProgress) -> Result<ImagePanelData, ImageProcessError> {
+    if parameters.window_size == 0 {
+        return Err(ImageProcessError { message: String::from("window size must be at least 1") });
+    }
+    let window_size = (parameters.window_size | 1) as i128;
+    check_window_size("window size", window_size as u64, dynamic_img.width(), dynamic_img.height())?;
+    let radius = window_size / 2;
+    let luma = Arc::new(dynamic_img.to_luma16());
+    let width = luma.width() as usize;
+    let local_statistics = match method {
+        AdaptiveMethod::Gaussian => {
+            progress.expect_passes(3);
+            LocalStatistics::Gaussian(filter::separable_convolve(&luma, &gaussian_weights(window_size), parameters.border, progress))
+        },
+        _ => LocalStatistics::Integral(IntegralImage::with_squares(&luma, radius as u32, parameters.border)),
+    };
+    progress.check()?;
     create_image_panel_data(
-        dynamic_img.clone(), 
-        |dynamic_img: Arc<DynamicImage>|{Arc::new(dynamic_img.clone().to_luma16())},
+        dynamic_img,
+        progress,
+        |_| luma.clone(),
         |x, y, image_buf| {
-        let mut filter_weight_sum = 0;
-        for i in (-FILTER_SIZE / 2)..(FILTER_SIZE / 2 + 1) {
-            for j in (-FILTER_SIZE / 2)..(FILTER_SIZE / 2 + 1) {
-                let filter_x = x as i128 + i;
-                let filter_y = y as i128 + j;
-                if filter_x < 0 || filter_x >= dynamic_img.width() as i128 ||
-                 filter_y < 0 || filter_y >= dynamic_img.height() as i128 {
-                    continue;
-                }
-                filter_weight_sum += image_buf.get_pixel(filter_x as u32, filter_y as u32)[0] as u32;
-            }
+        let (mean, std_dev) = match &local_statistics {
+            LocalStatistics::Gaussian(means) => (means[y as usize * width + x as usize], 0.0),
+            LocalStatistics::Integral(integral) => {
+                let statistics = integral.window(x, y, radius as u32);
+                (statistics.mean(), statistics.variance().sqrt())
+            },
+        };
+        let threshold = match method {
+            AdaptiveMethod::Mean | AdaptiveMethod::Gaussian => mean,
+            AdaptiveMethod::Sauvola => mean * (1.0 + parameters.k * (std_dev / SAUVOLA_DYNAMIC_RANGE - 1.0)),
+            AdaptiveMethod::Niblack => mean + parameters.k * std_dev,
+        } - parameters.offset;
+        if image_buf.get_pixel(x, y)[0] as f64 > threshold {
+            Luma([to_16_bit(parameters.max_color)])
+        } else {
+            Luma([0])
         }
-        let luminance_value = ((filter_weight_sum/(FILTER_SIZE*FILTER_SIZE)as u32) / 255) as u8;
-        Rgba([luminance_value, luminance_value, luminance_value, MAX_COLOR])   
     })
 }
 
 async fn process_none(dynamic_img: Arc<DynamicImage>) -> Result<ImagePanelData, ImageProcessError> {
     Ok(ImagePanelData {
-        image: dynamic_img
+        image: dynamic_img,
+        threshold: None,
+        origin: (0, 0),
     })
 }
 
-pub fn image_load(images: &mut HashMap<ImageType, ImagePanelData>, path: &str) -> Result<String, Box<dyn Error>>{
+async fn crop_image(image_panel_data: ImagePanelData, parameters: CropParameters, progress: &ProcessProgress) -> Result<ImagePanelData, ImageProcessError> {
+    progress.check()?;
+    let image = image_panel_data.get_image();
+    let Some((x, y, width, height)) = parameters.rectangle(image.width(), image.height()) else {
+        return Err(ImageProcessError { message: String::from("crop rectangle is outside the image") });
+    };
+    Ok(ImagePanelData {
+        image: Arc::new(image.crop_imm(x, y, width, height)),
+        threshold: image_panel_data.get_threshold(),
+        origin: (x, y),
+    })
+}
+
+/// Decodes the image at `path`.
+pub fn image_open(path: &str) -> Result<ImagePanelData, Box<dyn Error>>{
     let path = Path::new(path);
     if !path.exists() {
         return Err(Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, "file not found")));
     }
     
     let load_image = ImageReader::open(path)?.decode()?;
+    Ok(ImagePanelData::new(load_image))
+}
+
+/// Opens `path` as the Original image and its conversion with `method` as the Grayscale one.
+pub fn image_load(images: &mut HashMap<ImageType, ImagePanelData>, path: &str, method: GrayscaleMethod) -> Result<String, Box<dyn Error>>{
+    let image_panel_data = image_open(path)?;
     images.clear();
-    images.insert(ImageType::Original, ImagePanelData{image: Arc::new(load_image.clone())});
-    images.insert(ImageType::Grayscale, ImagePanelData{image: Arc::new(load_image)});
+    images.insert(ImageType::Grayscale, image_panel_data.to_grayscale(method));
+    images.insert(ImageType::Original, image_panel_data);
     Ok("OK".to_string())
 }
 
-pub async fn process_image(image_panel_data: ImagePanelData, process_type: ProcessType)  -> Result<ImagePanelData, ImageProcessError>{
-    
-    match process_type {
-        ProcessType::Binarization => binarize_image(image_panel_data.get_image()).await,
-        ProcessType::ConvolveFilterAVG => convolve_filter_avg(image_panel_data.get_image()).await,
-        ProcessType::None => process_none(image_panel_data.get_image()).await,
+/// Writes `image_panel_data` to `path`, choosing PNG, TIFF, BMP or JPEG by the
+/// file extension. `jpeg_quality` (1-100) is only used for JPEG files.
+pub fn image_save(image_panel_data: &ImagePanelData, path: &str, jpeg_quality: u8) -> Result<String, Box<dyn Error>> {
+    let path = Path::new(path);
+    let image = image_panel_data.get_image();
+    let format = ImageFormat::from_path(path)?;
+    match format {
+        ImageFormat::Png | ImageFormat::Tiff => {
+            match image.as_ref() {
+                DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+                    DynamicImage::ImageRgba16(image.to_rgba16()).save_with_format(path, format)?
+                },
+                _ => image.save_with_format(path, format)?,
+            }
+        },
+        ImageFormat::Bmp => DynamicImage::ImageRgba8(image.to_rgba8()).save_with_format(path, format)?,
+        ImageFormat::Jpeg => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            JpegEncoder::new_with_quality(&mut writer, jpeg_quality.clamp(1, 100))
+                .encode_image(&DynamicImage::ImageRgb8(image.to_rgb8()))?;
+        },
+        _ => return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidInput, "unsupported file format, use png, tiff, bmp or jpeg"))),
+    }
+    Ok("OK".to_string())
+}
+
+/// Runs one operation on `image_panel_data`, reporting its rows to `progress`.
+pub async fn process_image(image_panel_data: ImagePanelData, process_parameters: ProcessParameters, progress: &ProcessProgress)  -> Result<ImagePanelData, ImageProcessError>{
+    let origin = image_panel_data.get_origin();
+    let mut result = match process_parameters {
+        ProcessParameters::Crop(parameters) => crop_image(image_panel_data, parameters, progress).await,
+        ProcessParameters::Binarization(parameters) => binarize_image(image_panel_data.get_image(), parameters, progress).await,
+        ProcessParameters::OtsuBinarization(parameters) => otsu_binarize_image(image_panel_data.get_image(), parameters, progress).await,
+        ProcessParameters::AdaptiveBinarization { method, parameters } => adaptive_binarize_image(image_panel_data.get_image(), method, parameters, progress).await,
+        ProcessParameters::ConvolveFilterAVG(parameters) => filter::box_filter_image(image_panel_data.get_image(), parameters, progress).await,
+        ProcessParameters::GaussianBlur(parameters) => filter::gaussian_blur(image_panel_data.get_image(), parameters, progress).await,
+        ProcessParameters::RankFilter { rank, parameters } => filter::rank_filter_image(image_panel_data.get_image(), rank, parameters, progress).await,
+        ProcessParameters::EdgeDetection { operator, parameters } => edge::edge_image(image_panel_data.get_image(), operator, parameters, progress).await,
+        ProcessParameters::Canny(parameters) => edge::canny_image(image_panel_data.get_image(), parameters, progress).await,
+        ProcessParameters::None => process_none(image_panel_data.get_image()).await,
+    }?;
+    // Results are placed relative to their input; keep them relative to the first image.
+    result.origin = (origin.0 + result.origin.0, origin.1 + result.origin.1);
+    Ok(result)
+}
+
+/// Runs `steps` in order, feeding each step the result of the previous one, and
+/// returns every intermediate result so that each step can be inspected. The
+/// run stops with an error as soon as `progress` is cancelled.
+pub async fn process_pipeline(image_panel_data: ImagePanelData, steps: Vec<ProcessParameters>, progress: Arc<ProcessProgress>) -> Result<Vec<ImagePanelData>, ImageProcessError> {
+    if steps.is_empty() {
+        return Err(ImageProcessError { message: String::from("pipeline has no steps") });
+    }
+    let mut results: Vec<ImagePanelData> = Vec::with_capacity(steps.len());
+    let step_count = steps.len();
+    for (index, step) in steps.into_iter().enumerate() {
+        progress.check()?;
+        progress.begin_step(index, step_count);
+        let input = results.last().cloned().unwrap_or_else(|| image_panel_data.clone());
+        results.push(process_image(input, step, &progress).await?);
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{naive_sample, test_image, BORDERS};
+
+    #[test]
+    fn border_sample_matches_naive_reference() {
+        // Positions reach several image sizes past every edge, also of a single pixel.
+        for (width, height) in [(5, 3), (2, 1), (1, 1)] {
+            let luma = test_image(width, height);
+            for border in BORDERS {
+                for y in -7..10 {
+                    for x in -11..16 {
+                        assert_eq!(border.sample(&luma, x, y), naive_sample(&luma, border, x, y),
+                            "{:?} on {}x{} at ({}, {})", border, width, height, x, y);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn border_modes_read_the_documented_pixels() {
+        // abcd read from -3 to 6.
+        let luma: LumaBuffer = ImageBuffer::from_raw(4, 1, vec![1, 2, 3, 4]).unwrap();
+        let row = |border: BorderMode| (-3..7).map(|x| border.sample(&luma, x, 0)).collect::<Vec<_>>();
+        let some = |values: &[u16]| values.iter().map(|value| Some(*value)).collect::<Vec<_>>();
+        assert_eq!(row(BorderMode::Constant(9)), some(&[9, 9, 9, 1, 2, 3, 4, 9, 9, 9]));
+        assert_eq!(row(BorderMode::Replicate), some(&[1, 1, 1, 1, 2, 3, 4, 4, 4, 4]));
+        assert_eq!(row(BorderMode::Reflect), some(&[3, 2, 1, 1, 2, 3, 4, 4, 3, 2]));
+        assert_eq!(row(BorderMode::Wrap), some(&[2, 3, 4, 1, 2, 3, 4, 1, 2, 3]));
+        assert_eq!(row(BorderMode::NormalizeValid), [None, None, None, Some(1), Some(2), Some(3), Some(4), None, None, None]);
+    }
+
+    #[test]
+    fn window_size_is_limited_by_the_longer_side() {
+        assert!(check_window_size("window size", 7, 3, 1).is_ok());
+        assert!(check_window_size("window size", 9, 3, 1).is_err());
+        assert!(check_window_size("window size", u32::MAX as u64, 1, 1).is_err());
+    }
+
+    #[test]
+    fn otsu_threshold_splits_two_peaks() {
+        let mut histogram = vec![0u64; 65536];
+        for (value, count) in [(9000, 10), (10000, 40), (11000, 10), (50000, 20), (51000, 60), (52000, 20)] {
+            histogram[value] = count;
+        }
+        let threshold = otsu_threshold(&histogram);
+        // Every threshold between the peaks gives the same variance; the first is taken.
+        assert_eq!(threshold, 11000);
+    }
+
+    #[test]
+    fn otsu_threshold_of_a_flat_image_is_its_value() {
+        for value in [0, 1000, 65535] {
+            let mut histogram = vec![0u64; 65536];
+            histogram[value] = 100;
+            assert_eq!(otsu_threshold(&histogram), value as u16);
+        }
     }
 }
@@ -0,0 +1,92 @@
+//! Progress reporting and cancellation of a running pipeline.
+//!
+//! A pipeline is made of steps, and each step makes one or more passes over the
+//! rows of the image. The row-parallel helpers count the rows as they finish
+//! and skip the remaining ones once the run is cancelled.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::process::ImageProcessError;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct ProgressPosition {
+    step: usize,
+    steps: usize,
+    pass: usize,
+    passes: usize,
+    rows: usize,
+}
+
+/// Shared between a running pipeline and whoever watches or cancels it.
+#[derive(Debug, Default)]
+pub struct ProcessProgress {
+    cancelled: AtomicBool,
+    rows_done: AtomicUsize,
+    position: Mutex<ProgressPosition>,
+}
+
+impl ProcessProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the pipeline to stop; it returns an error as soon as it notices.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Fraction of the pipeline done so far, from 0 to 1.
+    pub fn fraction(&self) -> f32 {
+        let position = *self.position.lock().unwrap();
+        if position.steps == 0 {
+            return 0.0;
+        }
+        let rows_done = self.rows_done.load(Ordering::Relaxed).min(position.rows);
+        let pass_fraction = match position.rows {
+            0 => 0.0,
+            rows => rows_done as f32 / rows as f32,
+        };
+        let passes = position.passes.max(1);
+        let step_fraction = ((position.pass as f32 + pass_fraction) / passes as f32).min(1.0);
+        ((position.step as f32 + step_fraction) / position.steps as f32).min(1.0)
+    }
+
+    /// Returns the cancellation error once `cancel` has been called.
+    pub(crate) fn check(&self) -> Result<(), ImageProcessError> {
+        match self.is_cancelled() {
+            true => Err(ImageProcessError { message: String::from("cancelled") }),
+            false => Ok(()),
+        }
+    }
+
+    pub(crate) fn begin_step(&self, step: usize, steps: usize) {
+        *self.position.lock().unwrap() = ProgressPosition { step, steps, pass: 0, passes: 1, rows: 0 };
+        self.rows_done.store(0, Ordering::Relaxed);
+    }
+
+    /// Declares how many row passes the current step makes.
+    pub(crate) fn expect_passes(&self, passes: usize) {
+        self.position.lock().unwrap().passes = passes;
+    }
+
+    pub(crate) fn begin_pass(&self, rows: usize) {
+        self.position.lock().unwrap().rows = rows;
+        self.rows_done.store(0, Ordering::Relaxed);
+    }
+
+    pub(crate) fn row_done(&self) {
+        self.rows_done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn end_pass(&self) {
+        let mut position = self.position.lock().unwrap();
+        position.pass = (position.pass + 1).min(position.passes);
+        position.rows = 0;
+        self.rows_done.store(0, Ordering::Relaxed);
+    }
+}
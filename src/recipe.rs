@@ -0,0 +1,147 @@
+use std::{error::Error, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::process::ProcessParameters;
+
+/// Bumped whenever the layout of a recipe file changes incompatibly.
+pub const RECIPE_VERSION: u32 = 1;
+pub const RECIPE_FILE_EXTENTIONS: &[&str; 2] = &["json", "toml"];
+
+/// A processing pipeline as written to disk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recipe {
+    pub version: u32,
+    pub steps: Vec<ProcessParameters>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RecipeFormat {
+    Json,
+    Toml,
+}
+
+fn recipe_format(path: &Path) -> Result<RecipeFormat, Box<dyn Error>> {
+    match path.extension().and_then(|extension| extension.to_str()).map(|extension| extension.to_ascii_lowercase()) {
+        Some(extension) if extension == "json" => Ok(RecipeFormat::Json),
+        Some(extension) if extension == "toml" => Ok(RecipeFormat::Toml),
+        _ => Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidInput, "recipe file must end in .json or .toml"))),
+    }
+}
+
+pub fn recipe_save(steps: &[ProcessParameters], path: &str) -> Result<String, Box<dyn Error>> {
+    let path = Path::new(path);
+    let recipe = Recipe {
+        version: RECIPE_VERSION,
+        steps: steps.to_vec(),
+    };
+    let contents = match recipe_format(path)? {
+        RecipeFormat::Json => serde_json::to_string_pretty(&recipe)?,
+        RecipeFormat::Toml => toml::to_string_pretty(&recipe)?,
+    };
+    fs::write(path, contents)?;
+    Ok("OK".to_string())
+}
+
+pub fn recipe_load(path: &str) -> Result<Vec<ProcessParameters>, Box<dyn Error>> {
+    let path = Path::new(path);
+    if !path.exists() {
+        return Err(Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, "file not found")));
+    }
+
+    let contents = fs::read_to_string(path)?;
+    let recipe: Recipe = match recipe_format(path)? {
+        RecipeFormat::Json => serde_json::from_str(&contents)?,
+        RecipeFormat::Toml => toml::from_str(&contents)?,
+    };
+    if recipe.version > RECIPE_VERSION {
+        return Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("recipe version {} is newer than the supported version {}", recipe.version, RECIPE_VERSION),
+        )));
+    }
+    Ok(recipe.steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::{
+        AdaptiveMethod, AdaptiveParameters, BorderMode, CannyParameters, CropParameters, EdgeOperator, EdgeParameters,
+        FilterParameters, GaussianParameters, ProcessType, RankKind, RankParameters
+    };
+
+    /// A file in the temporary directory, removed when dropped.
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            TempFile(std::env::temp_dir().join(format!("simple-image-app-{}-{}", std::process::id(), name)))
+        }
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// The defaults of every process type, then steps with non-default values.
+    fn steps() -> Vec<ProcessParameters> {
+        let mut steps: Vec<ProcessParameters> = ProcessType::ALL.iter().map(ProcessType::default_parameters).collect();
+        steps.extend([
+            ProcessParameters::Crop(CropParameters { x: 3, y: 4, width: 0, height: 20 }),
+            ProcessParameters::AdaptiveBinarization {
+                method: AdaptiveMethod::Sauvola,
+                parameters: AdaptiveParameters { window_size: 31, offset: -12.5, border: BorderMode::Constant(1234), ..AdaptiveParameters::default_for(AdaptiveMethod::Sauvola) },
+            },
+            ProcessParameters::ConvolveFilterAVG(FilterParameters { filter_size: 7, border: BorderMode::Constant(0) }),
+            ProcessParameters::GaussianBlur(GaussianParameters { sigma: 2.5, kernel_size: 0, border: BorderMode::Constant(u16::MAX) }),
+            ProcessParameters::RankFilter { rank: RankKind::Percentile, parameters: RankParameters { percentile: 12.5, border: BorderMode::Wrap, ..RankParameters::default() } },
+            ProcessParameters::EdgeDetection { operator: EdgeOperator::Scharr, parameters: EdgeParameters { normalize: true, border: BorderMode::Constant(77), ..EdgeParameters::default() } },
+            ProcessParameters::Canny(CannyParameters { automatic: false, low_threshold: 1000, high_threshold: 3000, border: BorderMode::Reflect, ..CannyParameters::default() }),
+        ]);
+        steps
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let file = TempFile::new("recipe.json");
+        recipe_save(&steps(), file.path()).unwrap();
+        assert_eq!(recipe_load(file.path()).unwrap(), steps());
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        let file = TempFile::new("recipe.toml");
+        recipe_save(&steps(), file.path()).unwrap();
+        assert_eq!(recipe_load(file.path()).unwrap(), steps());
+    }
+
+    #[test]
+    fn border_defaults_when_missing() {
+        let file = TempFile::new("old.json");
+        fs::write(file.path(), r#"{"version": 1, "steps": [{"type": "GaussianBlur", "sigma": 2.0, "kernel_size": 0}]}"#).unwrap();
+        assert_eq!(
+            recipe_load(file.path()).unwrap(),
+            vec![ProcessParameters::GaussianBlur(GaussianParameters { sigma: 2.0, kernel_size: 0, border: BorderMode::default() })],
+        );
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let file = TempFile::new("newer.toml");
+        fs::write(file.path(), format!("version = {}\nsteps = []\n", RECIPE_VERSION + 1)).unwrap();
+        assert!(recipe_load(file.path()).is_err());
+    }
+
+    #[test]
+    fn unknown_extension_is_rejected() {
+        let file = TempFile::new("recipe.yaml");
+        assert!(recipe_save(&steps(), file.path()).is_err());
+        assert!(!file.0.exists());
+    }
+}
@@ -0,0 +1,185 @@
+//! Regions of interest: the part of an image that statistics are gathered from
+//! and, optionally, the only part that processing changes.
+
+use image_crate::{DynamicImage, ImageBuffer, Pixel};
+
+use crate::process::{ImagePanelData, ImageProcessError};
+
+/// Number of points an ellipse outline is drawn with.
+const ELLIPSE_OUTLINE_POINTS: usize = 72;
+
+/// A region in image coordinates, where pixel `(x, y)` covers `x..x + 1` and
+/// `y..y + 1`. A pixel is inside when its center is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Roi {
+    Rectangle { left: f32, top: f32, right: f32, bottom: f32 },
+    Ellipse { center: (f32, f32), radii: (f32, f32) },
+    Polygon(Vec<(f32, f32)>),
+}
+
+impl Roi {
+    /// The rectangle with opposite corners `a` and `b`.
+    pub fn rectangle(a: (f32, f32), b: (f32, f32)) -> Self {
+        Roi::Rectangle { left: a.0.min(b.0), top: a.1.min(b.1), right: a.0.max(b.0), bottom: a.1.max(b.1) }
+    }
+
+    /// The ellipse inscribed in the rectangle with opposite corners `a` and `b`.
+    pub fn ellipse(a: (f32, f32), b: (f32, f32)) -> Self {
+        Roi::Ellipse {
+            center: ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0),
+            radii: ((a.0 - b.0).abs() / 2.0, (a.1 - b.1).abs() / 2.0),
+        }
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        let (x, y) = (x as f32 + 0.5, y as f32 + 0.5);
+        match self {
+            Roi::Rectangle { left, top, right, bottom } => x >= *left && x < *right && y >= *top && y < *bottom,
+            Roi::Ellipse { center, radii } => {
+                if radii.0 <= 0.0 || radii.1 <= 0.0 {
+                    return false;
+                }
+                let (dx, dy) = ((x - center.0) / radii.0, (y - center.1) / radii.1);
+                dx * dx + dy * dy <= 1.0
+            },
+            // Even-odd rule: count the edges crossed by a ray to the right.
+            Roi::Polygon(points) => {
+                let mut inside = false;
+                for (index, &(x1, y1)) in points.iter().enumerate() {
+                    let (x2, y2) = points[(index + 1) % points.len()];
+                    if (y1 > y) != (y2 > y) && x < x1 + (y - y1) / (y2 - y1) * (x2 - x1) {
+                        inside = !inside;
+                    }
+                }
+                inside
+            },
+        }
+    }
+
+    /// Smallest `(left, top, right, bottom)` box holding the region.
+    pub fn bounds(&self) -> (f32, f32, f32, f32) {
+        self.outline().iter().fold(
+            (f32::INFINITY, f32::INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+            |(left, top, right, bottom), &(x, y)| (left.min(x), top.min(y), right.max(x), bottom.max(y)),
+        )
+    }
+
+    /// The same region moved by `(dx, dy)`.
+    pub fn translated(&self, dx: f32, dy: f32) -> Self {
+        match self {
+            Roi::Rectangle { left, top, right, bottom } => Roi::Rectangle { left: left + dx, top: top + dy, right: right + dx, bottom: bottom + dy },
+            Roi::Ellipse { center, radii } => Roi::Ellipse { center: (center.0 + dx, center.1 + dy), radii: *radii },
+            Roi::Polygon(points) => Roi::Polygon(points.iter().map(|(x, y)| (x + dx, y + dy)).collect()),
+        }
+    }
+
+    /// Closed outline of the region, for drawing it.
+    pub fn outline(&self) -> Vec<(f32, f32)> {
+        match self {
+            Roi::Rectangle { left, top, right, bottom } => vec![(*left, *top), (*right, *top), (*right, *bottom), (*left, *bottom)],
+            Roi::Ellipse { center, radii } => (0..ELLIPSE_OUTLINE_POINTS)
+                .map(|index| {
+                    let angle = index as f32 / ELLIPSE_OUTLINE_POINTS as f32 * std::f32::consts::TAU;
+                    (center.0 + radii.0 * angle.cos(), center.1 + radii.1 * angle.sin())
+                })
+                .collect(),
+            Roi::Polygon(points) => points.clone(),
+        }
+    }
+}
+
+/// Keeps `result` inside `roi` and `input` everywhere else, so that processing
+/// only changes the region. A cropped `result` is merged with the same part of
+/// `input`, found from the origins of the two; `roi` is in the coordinates of `input`.
+pub fn restrict_to_roi(input: &ImagePanelData, result: &ImagePanelData, roi: &Roi) -> Result<ImagePanelData, ImageProcessError> {
+    let (input_left, input_top) = input.get_origin();
+    let (result_left, result_top) = result.get_origin();
+    let result_image = result.get_image();
+    // Where the result starts in `input`, if it lies inside it.
+    let offset = |result_start: u32, input_start: u32, length: u32, input_length: usize| {
+        result_start.checked_sub(input_start).filter(|start| *start as usize + length as usize <= input_length)
+    };
+    let (Some(left), Some(top)) = (
+        offset(result_left, input_left, result_image.width(), input.get_image_width()),
+        offset(result_top, input_top, result_image.height(), input.get_image_height()),
+    ) else {
+        return Err(ImageProcessError { message: String::from("processing inside a ROI needs the result to lie inside the input image") });
+    };
+    let input_image = input.crop(left, top, result_image.width(), result_image.height()).get_image();
+    let roi = roi.translated(-(left as f32), -(top as f32));
+    let image = match &*result_image {
+        image if image.color().has_alpha() => DynamicImage::ImageRgba16(merge(&input_image.to_rgba16(), &image.to_rgba16(), &roi)),
+        image if image.color().has_color() => DynamicImage::ImageRgb16(merge(&input_image.to_rgb16(), &image.to_rgb16(), &roi)),
+        image => DynamicImage::ImageLuma16(merge(&input_image.to_luma16(), &image.to_luma16(), &roi)),
+    };
+    Ok(ImagePanelData { image: image.into(), threshold: result.get_threshold(), origin: result.get_origin() })
+}
+
+fn merge<P: Pixel>(outside: &ImageBuffer<P, Vec<P::Subpixel>>, inside: &ImageBuffer<P, Vec<P::Subpixel>>, roi: &Roi) -> ImageBuffer<P, Vec<P::Subpixel>> {
+    ImageBuffer::from_fn(inside.width(), inside.height(), |x, y| {
+        match roi.contains(x, y) {
+            true => *inside.get_pixel(x, y),
+            false => *outside.get_pixel(x, y),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::LumaBuffer;
+    use crate::test_util::test_image;
+    use image_crate::Luma;
+
+    fn inside(roi: &Roi, width: u32, height: u32) -> Vec<(u32, u32)> {
+        (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).filter(|(x, y)| roi.contains(*x, *y)).collect()
+    }
+
+    #[test]
+    fn pixels_count_by_their_center() {
+        assert_eq!(inside(&Roi::rectangle((1.0, 1.0), (3.0, 2.0)), 5, 4), [(1, 1), (2, 1)]);
+        // Covers most of pixel 1 but not its center.
+        assert_eq!(inside(&Roi::rectangle((0.6, 0.6), (1.4, 1.4)), 3, 3), []);
+        assert_eq!(inside(&Roi::rectangle((0.4, 0.4), (0.6, 0.6)), 3, 3), [(0, 0)]);
+        // Only the corner pixels of the circle's box have their center outside it.
+        let corners = [(0, 0), (3, 0), (0, 3), (3, 3)];
+        let square: Vec<(u32, u32)> = inside(&Roi::rectangle((0.0, 0.0), (4.0, 4.0)), 5, 5);
+        let expected: Vec<(u32, u32)> = square.into_iter().filter(|pixel| !corners.contains(pixel)).collect();
+        assert_eq!(inside(&Roi::ellipse((0.0, 0.0), (4.0, 4.0)), 5, 5), expected);
+    }
+
+    #[test]
+    fn polygon_uses_the_even_odd_rule() {
+        // A pentagram: the tips are inside, the pentagon in the middle is crossed twice and is not.
+        let points = (0..5)
+            .map(|index| {
+                let angle = (-90.0 + 144.0 * index as f32).to_radians();
+                (10.0 + 9.0 * angle.cos(), 10.0 + 9.0 * angle.sin())
+            })
+            .collect();
+        let star = Roi::Polygon(points);
+        assert!(star.contains(9, 5));
+        assert!(!star.contains(9, 9));
+        assert!(!star.contains(0, 0));
+    }
+
+    #[test]
+    fn cropped_result_is_merged_at_its_origin() {
+        let input = ImagePanelData::new(DynamicImage::ImageLuma16(test_image(6, 4)));
+        let cropped: LumaBuffer = ImageBuffer::from_pixel(3, 2, Luma([200]));
+        let result = ImagePanelData { origin: (2, 1), ..ImagePanelData::new(DynamicImage::ImageLuma16(cropped)) };
+        // Input columns 3 and on.
+        let roi = Roi::rectangle((3.0, 0.0), (6.0, 4.0));
+        let merged = restrict_to_roi(&input, &result, &roi).unwrap();
+        assert_eq!(merged.get_origin(), (2, 1));
+        let (merged, input) = (merged.get_image().to_luma16(), input.get_image().to_luma16());
+        assert_eq!(merged.dimensions(), (3, 2));
+        for (x, y, pixel) in merged.enumerate_pixels() {
+            let expected = if x == 0 { input.get_pixel(2, y + 1)[0] } else { 200 };
+            assert_eq!(pixel[0], expected, "({}, {})", x, y);
+        }
+
+        let outside = ImagePanelData { origin: (5, 3), ..result };
+        assert!(restrict_to_roi(&ImagePanelData::new(DynamicImage::ImageLuma16(test_image(6, 4))), &outside, &roi).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+//! Summary statistics of the values of an image, gathered in one pass over its
+//! pixels into full 16-bit histograms.
+
+use crate::process::{GrayscaleMethod, ImagePanelData};
+use crate::roi::Roi;
+
+const VALUE_COUNT: usize = u16::MAX as usize + 1;
+pub const DEFAULT_PERCENTILES: &[f64] = &[1.0, 5.0, 95.0, 99.0];
+
+/// Statistics of one channel, kept as a histogram of every 16-bit value.
+#[derive(Debug, Clone)]
+pub struct ChannelStatistics {
+    histogram: Vec<u64>,
+    count: u64,
+    min: u16,
+    max: u16,
+    mean: f64,
+    std_dev: f64,
+}
+
+impl ChannelStatistics {
+    fn from_histogram(histogram: Vec<u64>) -> Self {
+        let count: u64 = histogram.iter().sum();
+        let min = histogram.iter().position(|&n| n > 0).unwrap_or(0) as u16;
+        let max = histogram.iter().rposition(|&n| n > 0).unwrap_or(0) as u16;
+        let (sum, square_sum) = histogram.iter().enumerate().fold((0.0, 0.0), |(sum, square_sum), (value, &n)| {
+            let weighted = value as f64 * n as f64;
+            (sum + weighted, square_sum + weighted * value as f64)
+        });
+        let (mean, std_dev) = match count {
+            0 => (0.0, 0.0),
+            count => {
+                let mean = sum / count as f64;
+                (mean, (square_sum / count as f64 - mean * mean).max(0.0).sqrt())
+            },
+        };
+        ChannelStatistics { histogram, count, min, max, mean, std_dev }
+    }
+
+    /// Number of pixels counted.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min(&self) -> u16 {
+        self.min
+    }
+
+    pub fn max(&self) -> u16 {
+        self.max
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        self.std_dev
+    }
+
+    pub fn median(&self) -> u16 {
+        self.percentile(50.0)
+    }
+
+    /// Smallest value with at least `percent` percent of the pixels at or below it.
+    pub fn percentile(&self, percent: f64) -> u16 {
+        let rank = ((percent.clamp(0.0, 100.0) / 100.0 * self.count as f64).ceil() as u64).max(1);
+        let mut below = 0;
+        for (value, &n) in self.histogram.iter().enumerate() {
+            below += n;
+            if below >= rank {
+                return value as u16;
+            }
+        }
+        self.max
+    }
+
+    /// Shannon entropy of the 16-bit values, in bits.
+    pub fn entropy(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.histogram.iter()
+            .filter(|&&n| n > 0)
+            .map(|&n| {
+                let p = n as f64 / self.count as f64;
+                -p * p.log2()
+            })
+            .sum()
+    }
+
+    /// Number of pixels at the largest 16-bit value.
+    pub fn saturated(&self) -> u64 {
+        self.histogram[VALUE_COUNT - 1]
+    }
+
+    pub fn zero(&self) -> u64 {
+        self.histogram[0]
+    }
+
+    /// The histogram merged into `bins` bins of equal width.
+    pub fn histogram(&self, bins: usize) -> Vec<u64> {
+        let mut histogram = vec![0u64; bins];
+        for (value, &n) in self.histogram.iter().enumerate() {
+            histogram[value * bins / VALUE_COUNT] += n;
+        }
+        histogram
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImageStatistics {
+    pub width: u32,
+    pub height: u32,
+    /// 16-bit luminance, with the Rec.709 weights for color images.
+    pub luma: ChannelStatistics,
+    /// Red, green, blue and, when present, alpha of a color image; empty for a gray one.
+    pub channels: Vec<ChannelStatistics>,
+}
+
+impl ImagePanelData {
+    /// Statistics of the luminance and of every color channel.
+    pub fn statistics(&self) -> ImageStatistics {
+        self.statistics_where(|_, _| true)
+    }
+
+    /// Like `statistics`, counting only the pixels inside `roi`.
+    pub fn roi_statistics(&self, roi: &Roi) -> ImageStatistics {
+        self.statistics_where(|x, y| roi.contains(x, y))
+    }
+
+    fn statistics_where(&self, include: impl Fn(u32, u32) -> bool) -> ImageStatistics {
+        let (width, height) = (self.image.width(), self.image.height());
+        let color = self.image.color();
+        if !color.has_color() {
+            let mut histogram = vec![0u64; VALUE_COUNT];
+            for (x, y, pixel) in self.image.to_luma16().enumerate_pixels() {
+                if include(x, y) {
+                    histogram[pixel[0] as usize] += 1;
+                }
+            }
+            return ImageStatistics {
+                width,
+                height,
+                luma: ChannelStatistics::from_histogram(histogram),
+                channels: Vec::new(),
+            };
+        }
+
+        let channel_count = if color.has_alpha() { 4 } else { 3 };
+        let mut luma = vec![0u64; VALUE_COUNT];
+        let mut channels = vec![vec![0u64; VALUE_COUNT]; channel_count];
+        for (_, _, pixel) in self.image.to_rgba16().enumerate_pixels().filter(|(x, y, _)| include(*x, *y)) {
+            luma[GrayscaleMethod::Rec709.luma(pixel[0], pixel[1], pixel[2]) as usize] += 1;
+            for (channel, histogram) in channels.iter_mut().enumerate() {
+                histogram[pixel[channel] as usize] += 1;
+            }
+        }
+        ImageStatistics {
+            width,
+            height,
+            luma: ChannelStatistics::from_histogram(luma),
+            channels: channels.into_iter().map(ChannelStatistics::from_histogram).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statistics_of(values: &[u16]) -> ChannelStatistics {
+        let mut histogram = vec![0u64; VALUE_COUNT];
+        for value in values {
+            histogram[*value as usize] += 1;
+        }
+        ChannelStatistics::from_histogram(histogram)
+    }
+
+    #[test]
+    fn four_values_give_the_expected_summary() {
+        let statistics = statistics_of(&[0, 10, 20, 65535]);
+        assert_eq!(statistics.count(), 4);
+        assert_eq!((statistics.min(), statistics.max()), (0, 65535));
+        assert_eq!(statistics.median(), 10);
+        assert_eq!(statistics.entropy(), 2.0);
+        assert_eq!(statistics.saturated(), 1);
+        assert_eq!(statistics.zero(), 1);
+    }
+
+    #[test]
+    fn percentile_is_the_smallest_value_covering_the_rank() {
+        let statistics = statistics_of(&[0, 10, 20, 65535]);
+        assert_eq!(statistics.percentile(0.0), 0);
+        assert_eq!(statistics.percentile(25.0), 0);
+        assert_eq!(statistics.percentile(26.0), 10);
+        assert_eq!(statistics.percentile(75.0), 20);
+        assert_eq!(statistics.percentile(100.0), 65535);
+    }
+
+    #[test]
+    fn constant_values_have_no_entropy() {
+        let statistics = statistics_of(&[500; 8]);
+        assert_eq!(statistics.entropy(), 0.0);
+        assert_eq!(statistics.median(), 500);
+        assert_eq!((statistics.saturated(), statistics.zero()), (0, 0));
+        assert_eq!(statistics.std_dev(), 0.0);
+    }
+}
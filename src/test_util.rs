@@ -0,0 +1,48 @@
+//! Reference implementations the unit tests compare the fast code paths with.
+
+use image_crate::{ImageBuffer, Luma};
+
+use crate::process::{BorderMode, LumaBuffer};
+
+/// Every border mode, with a constant that differs from the image values.
+pub(crate) const BORDERS: [BorderMode; 5] = [
+    BorderMode::Constant(40000),
+    BorderMode::Replicate,
+    BorderMode::Reflect,
+    BorderMode::Wrap,
+    BorderMode::NormalizeValid,
+];
+
+/// A small image of irregular values, including 0 and 65535.
+pub(crate) fn test_image(width: u32, height: u32) -> LumaBuffer {
+    ImageBuffer::from_fn(width, height, |x, y| match (x, y) {
+        (0, 0) => Luma([0]),
+        (1, 0) => Luma([u16::MAX]),
+        _ => Luma([((x * 7919 + y * 104729 + x * y * 31) % 65536) as u16]),
+    })
+}
+
+/// Value read at (x, y) under `border`, found by stepping back into the image
+/// one reflection or period at a time instead of with `BorderMode::locate`.
+pub(crate) fn naive_sample(luma: &LumaBuffer, border: BorderMode, x: i64, y: i64) -> Option<u16> {
+    let fold = |mut position: i64, length: i64| -> Option<i64> {
+        while !(0..length).contains(&position) {
+            position = match border {
+                BorderMode::Constant(_) | BorderMode::NormalizeValid => return None,
+                BorderMode::Replicate => position.clamp(0, length - 1),
+                BorderMode::Reflect if position < 0 => -position - 1,
+                BorderMode::Reflect => 2 * length - 1 - position,
+                BorderMode::Wrap if position < 0 => position + length,
+                BorderMode::Wrap => position - length,
+            };
+        }
+        Some(position)
+    };
+    match (fold(x, luma.width() as i64), fold(y, luma.height() as i64)) {
+        (Some(x), Some(y)) => Some(luma.get_pixel(x as u32, y as u32)[0]),
+        _ => match border {
+            BorderMode::Constant(value) => Some(value),
+            _ => None,
+        },
+    }
+}
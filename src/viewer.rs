@@ -0,0 +1,474 @@
+//! Image panel widget with mouse-wheel zoom, drag to pan, drawing of a region
+//! of interest and a report of the pixel under the cursor.
+//!
+//! The zoom and scroll position live in the application as a `PanelView`, so
+//! that several panels can share one. Once a panel is zoomed far enough in to
+//! see single pixels, they are drawn as solid squares instead of the smoothed
+//! texture.
+
+use std::fmt;
+use std::sync::Arc;
+
+use iced::advanced::image::Renderer as ImageRenderer;
+use iced::advanced::layout::{self, Layout};
+use iced::advanced::renderer::{self, Quad, Renderer as _};
+use iced::advanced::widget::tree::{self, Tree};
+use iced::advanced::{Clipboard, Shell, Widget};
+use iced::event::{self, Event};
+use iced::mouse;
+use iced::widget::image::Handle;
+use iced::{Color, Element, Length, Point, Rectangle, Renderer, Size};
+use simple_image_app::image::RgbaImage;
+use simple_image_app::process::ImagePanelData;
+use simple_image_app::roi::Roi;
+
+const MIN_ZOOM: f32 = 0.01;
+const MAX_ZOOM: f32 = 64.0;
+const ZOOM_STEP: f32 = 1.25;
+/// Largest number of visible pixels drawn one square at a time.
+const MAX_PIXEL_SQUARES: usize = 40_000;
+/// Screen distance the cursor moves before a freehand polygon gets a new point.
+const FREEHAND_SPACING: f32 = 4.0;
+const ROI_COLOR: Color = Color { r: 1.0, g: 0.85, b: 0.0, a: 1.0 };
+const ROI_LINE_WIDTH: f32 = 2.0;
+
+/// What dragging the left mouse button over a panel does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoiTool {
+    /// Pans the view.
+    #[default]
+    Pan,
+    Rectangle,
+    Ellipse,
+    /// Adds a point per click, or follows the cursor while dragging; a right
+    /// click closes it.
+    Polygon,
+}
+impl RoiTool {
+    pub const ALL: &'static [Self] = &[
+        Self::Pan,
+        Self::Rectangle,
+        Self::Ellipse,
+        Self::Polygon,
+    ];
+}
+impl fmt::Display for RoiTool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoiTool::Pan => write!(f, "Pan"),
+            RoiTool::Rectangle => write!(f, "Rectangle ROI"),
+            RoiTool::Ellipse => write!(f, "Ellipse ROI"),
+            RoiTool::Polygon => write!(f, "Polygon ROI"),
+        }
+    }
+}
+
+/// Zoom and scroll position of an image panel.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PanelView {
+    /// Screen pixels per image pixel; `None` fits the whole image in the panel.
+    pub zoom: Option<f32>,
+    /// Image position shown at the center of the panel; `None` is the image center.
+    pub center: Option<Point>,
+}
+
+impl PanelView {
+    pub const FIT: PanelView = PanelView { zoom: None, center: None };
+    pub const ACTUAL_SIZE: PanelView = PanelView { zoom: Some(1.0), center: None };
+
+    fn scale(&self, image_size: Size, panel_size: Size) -> f32 {
+        self.zoom.unwrap_or_else(|| (panel_size.width / image_size.width).min(panel_size.height / image_size.height))
+    }
+
+    fn center(&self, image_size: Size) -> Point {
+        self.center.unwrap_or(Point::new(image_size.width / 2.0, image_size.height / 2.0))
+    }
+}
+
+/// What a panel draws for one image: the texture and the pixels behind it.
+#[derive(Debug, Clone)]
+pub struct PanelDisplay {
+    handle: Handle,
+    pixels: Arc<RgbaImage>,
+}
+
+impl PanelDisplay {
+    pub fn new(image_panel_data: &ImagePanelData) -> Self {
+        let pixels = image_panel_data.to_display_image();
+        PanelDisplay {
+            handle: Handle::from_pixels(pixels.width(), pixels.height(), pixels.as_raw().clone()),
+            pixels: Arc::new(pixels),
+        }
+    }
+
+    fn size(&self) -> Size {
+        Size::new(self.pixels.width() as f32, self.pixels.height() as f32)
+    }
+}
+
+/// Pixel under the cursor, `None` once the cursor leaves the image.
+type HoverHandler<Message> = Box<dyn Fn(Option<(u32, u32)>) -> Message>;
+
+pub struct ZoomImage<Message> {
+    display: PanelDisplay,
+    view: PanelView,
+    height: Length,
+    on_change: Box<dyn Fn(PanelView) -> Message>,
+    on_hover: Option<HoverHandler<Message>>,
+    roi: Option<Roi>,
+    roi_tool: RoiTool,
+    on_roi: Option<Box<dyn Fn(Roi) -> Message>>,
+}
+
+impl<Message> ZoomImage<Message> {
+    pub fn new(display: &PanelDisplay, view: PanelView, on_change: impl Fn(PanelView) -> Message + 'static) -> Self {
+        ZoomImage {
+            display: display.clone(),
+            view,
+            height: Length::Fill,
+            on_change: Box::new(on_change),
+            on_hover: None,
+            roi: None,
+            roi_tool: RoiTool::Pan,
+            on_roi: None,
+        }
+    }
+
+    /// Reports the image pixel under the cursor whenever it changes.
+    pub fn on_hover(mut self, on_hover: impl Fn(Option<(u32, u32)>) -> Message + 'static) -> Self {
+        self.on_hover = Some(Box::new(on_hover));
+        self
+    }
+
+    /// Outlines `roi` on the image.
+    pub fn roi(mut self, roi: Option<Roi>) -> Self {
+        self.roi = roi;
+        self
+    }
+
+    /// Draws a new region with `tool` instead of panning, and reports it once
+    /// it is finished.
+    pub fn on_roi(mut self, tool: RoiTool, on_roi: impl Fn(Roi) -> Message + 'static) -> Self {
+        self.roi_tool = tool;
+        self.on_roi = Some(Box::new(on_roi));
+        self
+    }
+
+    pub fn height(mut self, height: impl Into<Length>) -> Self {
+        self.height = height.into();
+        self
+    }
+
+    /// Keeps the center of the view on the image.
+    fn clamped(&self, view: PanelView) -> PanelView {
+        let image_size = self.display.size();
+        PanelView {
+            center: view.center.map(|center| Point::new(
+                center.x.clamp(0.0, image_size.width),
+                center.y.clamp(0.0, image_size.height),
+            )),
+            ..view
+        }
+    }
+
+    /// The region being drawn from `points`, before it is finished.
+    fn drawn_roi(&self, points: &[Point]) -> Option<Roi> {
+        let to_tuple = |point: &Point| (point.x, point.y);
+        match (self.roi_tool, points) {
+            (RoiTool::Rectangle, [first, .., last]) if first != last => Some(Roi::rectangle(to_tuple(first), to_tuple(last))),
+            (RoiTool::Ellipse, [first, .., last]) if first != last => Some(Roi::ellipse(to_tuple(first), to_tuple(last))),
+            (RoiTool::Polygon, [_, _, _, ..]) => Some(Roi::Polygon(points.iter().map(to_tuple).collect())),
+            _ => None,
+        }
+    }
+}
+
+fn to_image(position: Point, bounds: Rectangle, center: Point, scale: f32) -> Point {
+    center + (position - bounds.center()) * (1.0 / scale)
+}
+
+#[derive(Default)]
+struct State {
+    /// Where a drag started, on screen and on the image.
+    grab: Option<(Point, Point)>,
+    hovered: Option<(u32, u32)>,
+    /// Image points of the region being drawn.
+    drawing: Vec<Point>,
+    /// Whether the left button is down while drawing.
+    dragging: bool,
+}
+
+impl<Message> Widget<Message, Renderer> for ZoomImage<Message> {
+    fn tag(&self) -> tree::Tag {
+        tree::Tag::of::<State>()
+    }
+
+    fn state(&self) -> tree::State {
+        tree::State::new(State::default())
+    }
+
+    fn width(&self) -> Length {
+        Length::Fill
+    }
+
+    fn height(&self) -> Length {
+        self.height
+    }
+
+    fn layout(&self, _renderer: &Renderer, limits: &layout::Limits) -> layout::Node {
+        layout::Node::new(limits.width(Length::Fill).height(self.height).max())
+    }
+
+    fn on_event(
+        &mut self,
+        tree: &mut Tree,
+        event: Event,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        _renderer: &Renderer,
+        _clipboard: &mut dyn Clipboard,
+        shell: &mut Shell<'_, Message>,
+        _viewport: &Rectangle,
+    ) -> event::Status {
+        let bounds = layout.bounds();
+        let image_size = self.display.size();
+        let scale = self.view.scale(image_size, bounds.size());
+        let center = self.view.center(image_size);
+        let state = tree.state.downcast_mut::<State>();
+
+        match event {
+            Event::Mouse(mouse::Event::WheelScrolled { delta }) => {
+                let Some(cursor_position) = cursor.position_over(bounds) else {
+                    return event::Status::Ignored;
+                };
+                let (mouse::ScrollDelta::Lines { y, .. } | mouse::ScrollDelta::Pixels { y, .. }) = delta;
+                let zoom = match y {
+                    y if y > 0.0 => scale * ZOOM_STEP,
+                    y if y < 0.0 => scale / ZOOM_STEP,
+                    _ => return event::Status::Ignored,
+                }.clamp(MIN_ZOOM, MAX_ZOOM);
+                // Keeps the image point under the cursor in place.
+                let cursor_offset = cursor_position - bounds.center();
+                let cursor_on_image = center + cursor_offset * (1.0 / scale);
+                let view = PanelView {
+                    zoom: Some(zoom),
+                    center: Some(cursor_on_image - cursor_offset * (1.0 / zoom)),
+                };
+                shell.publish((self.on_change)(self.clamped(view)));
+                event::Status::Captured
+            },
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                let Some(cursor_position) = cursor.position_over(bounds) else {
+                    return event::Status::Ignored;
+                };
+                let point = to_image(cursor_position, bounds, center, scale);
+                match self.roi_tool {
+                    RoiTool::Rectangle | RoiTool::Ellipse => state.drawing = vec![point, point],
+                    RoiTool::Polygon => state.drawing.push(point),
+                    RoiTool::Pan => state.grab = Some((cursor_position, center)),
+                }
+                state.dragging = true;
+                event::Status::Captured
+            },
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
+                state.dragging = false;
+                if state.grab.take().is_some() {
+                    return event::Status::Captured;
+                }
+                if self.roi_tool == RoiTool::Polygon || state.drawing.is_empty() {
+                    return event::Status::Ignored;
+                }
+                let points = std::mem::take(&mut state.drawing);
+                if let (Some(roi), Some(on_roi)) = (self.drawn_roi(&points), &self.on_roi) {
+                    shell.publish(on_roi(roi));
+                }
+                event::Status::Captured
+            },
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Right)) => {
+                if self.roi_tool != RoiTool::Polygon || state.drawing.is_empty() {
+                    return event::Status::Ignored;
+                }
+                let points = std::mem::take(&mut state.drawing);
+                if let (Some(roi), Some(on_roi)) = (self.drawn_roi(&points), &self.on_roi) {
+                    shell.publish(on_roi(roi));
+                }
+                event::Status::Captured
+            },
+            Event::Mouse(mouse::Event::CursorMoved { position }) => {
+                let hovered = cursor.position_over(bounds)
+                    .map(|position| to_image(position, bounds, center, scale))
+                    .filter(|point| point.x >= 0.0 && point.y >= 0.0 && point.x < image_size.width && point.y < image_size.height)
+                    .map(|point| (point.x as u32, point.y as u32));
+                if hovered != state.hovered {
+                    state.hovered = hovered;
+                    if let Some(on_hover) = &self.on_hover {
+                        shell.publish(on_hover(hovered));
+                    }
+                }
+                if state.dragging && !state.drawing.is_empty() {
+                    let point = to_image(position, bounds, center, scale);
+                    match self.roi_tool {
+                        RoiTool::Rectangle | RoiTool::Ellipse => state.drawing[1] = point,
+                        _ => {
+                            let last = state.drawing[state.drawing.len() - 1];
+                            if last.distance(point) * scale >= FREEHAND_SPACING {
+                                state.drawing.push(point);
+                            }
+                        },
+                    }
+                    return event::Status::Captured;
+                }
+                let Some((grab_position, grab_center)) = state.grab else {
+                    return event::Status::Ignored;
+                };
+                let view = PanelView {
+                    zoom: Some(scale),
+                    center: Some(grab_center - (position - grab_position) * (1.0 / scale)),
+                };
+                shell.publish((self.on_change)(self.clamped(view)));
+                event::Status::Captured
+            },
+            _ => event::Status::Ignored,
+        }
+    }
+
+    fn mouse_interaction(
+        &self,
+        tree: &Tree,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        _viewport: &Rectangle,
+        _renderer: &Renderer,
+    ) -> mouse::Interaction {
+        let state = tree.state.downcast_ref::<State>();
+        if state.grab.is_some() {
+            mouse::Interaction::Grabbing
+        } else if cursor.is_over(layout.bounds()) && self.roi_tool != RoiTool::Pan {
+            mouse::Interaction::Crosshair
+        } else if cursor.is_over(layout.bounds()) {
+            mouse::Interaction::Grab
+        } else {
+            mouse::Interaction::Idle
+        }
+    }
+
+    fn draw(
+        &self,
+        tree: &Tree,
+        renderer: &mut Renderer,
+        _theme: &iced::Theme,
+        _style: &renderer::Style,
+        layout: Layout<'_>,
+        _cursor: mouse::Cursor,
+        _viewport: &Rectangle,
+    ) {
+        let bounds = layout.bounds();
+        let image_size = self.display.size();
+        let scale = self.view.scale(image_size, bounds.size());
+        let center = self.view.center(image_size);
+        let origin = Point::new(bounds.center_x() - center.x * scale, bounds.center_y() - center.y * scale);
+
+        // Range of image pixels that are at least partly inside the panel.
+        let first_x = ((bounds.x - origin.x) / scale).floor().max(0.0) as u32;
+        let first_y = ((bounds.y - origin.y) / scale).floor().max(0.0) as u32;
+        let end_x = ((bounds.x + bounds.width - origin.x) / scale).ceil().clamp(0.0, image_size.width) as u32;
+        let end_y = ((bounds.y + bounds.height - origin.y) / scale).ceil().clamp(0.0, image_size.height) as u32;
+        let visible_pixels = end_x.saturating_sub(first_x) as usize * end_y.saturating_sub(first_y) as usize;
+
+        renderer.with_layer(bounds, |renderer| {
+            if scale > 1.0 && visible_pixels <= MAX_PIXEL_SQUARES {
+                for y in first_y..end_y {
+                    for x in first_x..end_x {
+                        let [red, green, blue, alpha] = self.display.pixels.get_pixel(x, y).0;
+                        let quad = Quad {
+                            bounds: Rectangle {
+                                x: origin.x + x as f32 * scale,
+                                y: origin.y + y as f32 * scale,
+                                width: scale,
+                                height: scale,
+                            },
+                            border_radius: 0.0.into(),
+                            border_width: 0.0,
+                            border_color: Color::TRANSPARENT,
+                        };
+                        renderer.fill_quad(quad, Color::from_rgba8(red, green, blue, alpha as f32 / 255.0));
+                    }
+                }
+            } else {
+                let image_bounds = Rectangle::new(origin, Size::new(image_size.width * scale, image_size.height * scale));
+                ImageRenderer::draw(renderer, self.display.handle.clone(), image_bounds);
+            }
+
+            let to_screen = |&(x, y): &(f32, f32)| Point::new(origin.x + x * scale, origin.y + y * scale);
+            if let Some(roi) = &self.roi {
+                draw_path(renderer, &roi.outline().iter().map(to_screen).collect::<Vec<_>>(), true, bounds);
+            }
+            let drawing = &tree.state.downcast_ref::<State>().drawing;
+            match self.drawn_roi(drawing) {
+                Some(Roi::Polygon(points)) => draw_path(renderer, &points.iter().map(to_screen).collect::<Vec<_>>(), false, bounds),
+                Some(roi) => draw_path(renderer, &roi.outline().iter().map(to_screen).collect::<Vec<_>>(), true, bounds),
+                None => {},
+            }
+        });
+    }
+}
+
+/// Draws the lines between `points` as small squares, skipping what lies
+/// outside `bounds`.
+fn draw_path(renderer: &mut Renderer, points: &[Point], closed: bool, bounds: Rectangle) {
+    let segment_count = match closed {
+        true => points.len(),
+        false => points.len().saturating_sub(1),
+    };
+    for index in 0..segment_count {
+        let Some((start, end)) = clip_segment(points[index], points[(index + 1) % points.len()], bounds) else {
+            continue;
+        };
+        let steps = start.distance(end).ceil().max(1.0) as usize;
+        for step in 0..=steps {
+            let t = step as f32 / steps as f32;
+            let quad = Quad {
+                bounds: Rectangle {
+                    x: start.x + (end.x - start.x) * t - ROI_LINE_WIDTH / 2.0,
+                    y: start.y + (end.y - start.y) * t - ROI_LINE_WIDTH / 2.0,
+                    width: ROI_LINE_WIDTH,
+                    height: ROI_LINE_WIDTH,
+                },
+                border_radius: 0.0.into(),
+                border_width: 0.0,
+                border_color: Color::TRANSPARENT,
+            };
+            renderer.fill_quad(quad, ROI_COLOR);
+        }
+    }
+}
+
+/// The part of the segment from `start` to `end` inside `bounds` (Liang-Barsky).
+fn clip_segment(start: Point, end: Point, bounds: Rectangle) -> Option<(Point, Point)> {
+    let (dx, dy) = (end.x - start.x, end.y - start.y);
+    let (mut t0, mut t1) = (0.0f32, 1.0f32);
+    let edges = [
+        (-dx, start.x - bounds.x),
+        (dx, bounds.x + bounds.width - start.x),
+        (-dy, start.y - bounds.y),
+        (dy, bounds.y + bounds.height - start.y),
+    ];
+    for (p, q) in edges {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
+        }
+    }
+    (t0 <= t1).then(|| (Point::new(start.x + dx * t0, start.y + dy * t0), Point::new(start.x + dx * t1, start.y + dy * t1)))
+}
+
+impl<'a, Message: 'a> From<ZoomImage<Message>> for Element<'a, Message> {
+    fn from(zoom_image: ZoomImage<Message>) -> Self {
+        Element::new(zoom_image)
+    }
+}